@@ -0,0 +1,98 @@
+//! Times the day 9 BOOST program, the heaviest intcode run we have. Compares
+//! the arithmetic decoder against the old string formatting one, and the
+//! interpreter against the cached block engine.
+//!
+//! Run with `cargo bench`.
+use aoc19::intcode::{BlockCache, Instruction, Program, State};
+
+use std::collections::VecDeque;
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+fn load(path: &str) -> Vec<i64> {
+    fs::read_to_string(path)
+        .unwrap()
+        .trim()
+        .split(',')
+        .map(|a| a.parse::<i64>().unwrap())
+        .collect()
+}
+
+/// Runs `f` `iterations` times and returns the mean duration
+fn time<F: FnMut()>(iterations: u32, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    start.elapsed() / iterations
+}
+
+/// The decoder `Program::step` used before, kept here as the baseline
+fn decode_formatted(raw: i64) -> (i64, u8, u8, u8) {
+    let instruction = format!("{:0>6}", raw);
+    let n = instruction.len();
+    let opcode = instruction[n - 2..n].parse::<i64>().unwrap();
+    let mode_a = instruction[n - 3..n - 2].parse::<u8>().unwrap();
+    let mode_b = instruction[n - 4..n - 3].parse::<u8>().unwrap();
+    let mode_c = instruction[n - 5..n - 4].parse::<u8>().unwrap();
+    (opcode, mode_a, mode_b, mode_c)
+}
+
+fn main() {
+    let data = load("input/2019/day9.txt");
+
+    // Every instruction the BOOST run executes, in order
+    let mut executed = Vec::new();
+    let mut program = Program::new(data.clone(), Vec::new());
+    let mut input = VecDeque::from(vec![2]);
+    loop {
+        executed.push(program.memory.get(program.pointer));
+        if program.step(&mut input).unwrap() == State::Halted {
+            break;
+        }
+    }
+
+    let formatted = time(10, || {
+        for raw in &executed {
+            black_box(decode_formatted(black_box(*raw)));
+        }
+    });
+    let arithmetic = time(10, || {
+        for raw in &executed {
+            black_box(Instruction::decode(0, black_box(*raw)).unwrap());
+        }
+    });
+    println!(
+        "decode {} instructions: formatted {:?}, arithmetic {:?} ({:.1}x)",
+        executed.len(),
+        formatted,
+        arithmetic,
+        formatted.as_secs_f64() / arithmetic.as_secs_f64()
+    );
+
+    let run = |blocks: bool| {
+        let mut program = Program::new(data.clone(), Vec::new());
+        if blocks {
+            program.block_cache = Some(BlockCache::new());
+        }
+        program.run(&mut VecDeque::from(vec![2])).unwrap();
+        program
+    };
+    assert_eq!(run(true).output, run(false).output);
+    let interpreted = time(10, || {
+        black_box(run(false).output);
+    });
+    let blocks = time(10, || {
+        black_box(run(true).output);
+    });
+    let cache = run(true).block_cache.unwrap();
+    println!(
+        "day 9 part 2 run: interpreter {:?}, blocks {:?} ({:.1}x, {} blocks decoded, {} runs)",
+        interpreted,
+        blocks,
+        interpreted.as_secs_f64() / blocks.as_secs_f64(),
+        cache.misses,
+        cache.hits + cache.misses
+    );
+}
@@ -0,0 +1,404 @@
+//! Command line tools for working with intcode programs
+use aoc19::intcode::debugger::Debugger;
+use aoc19::intcode::{
+    asm, cfg, disasm, transpile, BlockCache, Coverage, Heatmap, LoopDetector, Opcode, Overflow,
+    Profiler, Program, Protection, SelfModDetector, Tracer,
+};
+
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::io::{self, BufReader, BufWriter};
+use std::ops::Range;
+use std::process;
+
+const USAGE: &str = "usage: intcode <command> [options] <file> [input...]
+
+commands:
+    asm       assemble a source file into a comma separated program
+    cfg       print the control-flow graph of the program as Graphviz DOT
+    coverage  run the program once per input set, separated by --, and
+              show which instructions ran
+    debug     step through the program interactively
+    disasm    print a disassembly listing of the program
+    heatmap   run the program on the inputs and print per-cell access counts
+    profile   run the program on the inputs and report where time went
+    run       run the program on the inputs and print its output
+    selfmod   run the program on the inputs and report writes onto code
+    trace     run the program on the inputs, printing every instruction
+    transpile print the program as a Rust module with a native run function
+
+options for coverage, debug, heatmap, profile, run, selfmod and trace:
+    --resume         <file> is a snapshot to continue from, not a program
+                     (not coverage)
+    --save <path>    write a snapshot to path when the run stops (run only)
+    --blocks         run on cached pre-decoded blocks (run only)
+    --fuel <n>       stop after executing n instructions (all but debug)
+    --loops          stop on a proven infinite loop (all but debug)
+    --ppm <path>     also write the heatmap as a color image (heatmap only)
+    --pgm <path>     also write the heatmap as a grayscale image (heatmap only)
+    --width <n>      cells per image row, default 64 (heatmap only)
+    --fault          stop with an error on the first write onto code
+                     (selfmod only)
+    --addresses <a>-<b>
+                     only trace instructions at addresses a to b inclusive
+                     (trace only)
+    --opcodes <op,...>
+                     only trace instructions with these mnemonics, like
+                     add,out (trace only)
+    --read-only <a>-<b>
+                     fault on writes to addresses a to b inclusive, may be
+                     repeated (all but debug)
+    --no-execute <a>-<b>
+                     fault on executing addresses a to b inclusive, may be
+                     repeated (all but debug)
+    --overflow <policy>
+                     checked (the default), wrapping or saturating
+                     arithmetic (all but debug)";
+
+fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1)
+    })
+}
+
+/// Reads a comma separated intcode program
+fn load(path: &str) -> Vec<i64> {
+    read(path)
+        .trim()
+        .split(',')
+        .map(|a| {
+            a.trim().parse::<i64>().unwrap_or_else(|e| {
+                eprintln!("{}: bad cell {:?}: {}", path, a, e);
+                process::exit(1)
+            })
+        })
+        .collect()
+}
+
+/// Loads a program, or restores one from a snapshot
+fn start(path: &str, resume: bool) -> Program {
+    if !resume {
+        return Program::new(load(path), Vec::new());
+    }
+    let file = fs::File::open(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1)
+    });
+    Program::load(BufReader::new(file)).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1)
+    })
+}
+
+/// Creates an output file, exiting on failure
+fn create(path: &str) -> BufWriter<fs::File> {
+    match fs::File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1)
+        }
+    }
+}
+
+/// Removes `name` from the arguments, returning whether it was there
+fn switch(args: &mut Vec<&str>, name: &str) -> bool {
+    let found = args.iter().position(|a| *a == name);
+    found.map(|i| args.remove(i)).is_some()
+}
+
+/// Removes `name` and the value after it from the arguments
+fn option<'a>(args: &mut Vec<&'a str>, name: &str) -> Option<&'a str> {
+    let i = args.iter().position(|a| *a == name)?;
+    if i + 1 == args.len() {
+        eprintln!("{} needs a value", name);
+        process::exit(2)
+    }
+    args.remove(i);
+    Some(args.remove(i))
+}
+
+/// Parses an inclusive address range like `0-99`
+fn range(arg: &str) -> Range<usize> {
+    let bounds = match arg.split_once('-') {
+        Some((a, b)) => a.parse::<usize>().and_then(|a| Ok((a, b.parse()?))),
+        None => arg.parse::<usize>().map(|a| (a, a)),
+    };
+    let parsed = bounds.map_err(|e| e.to_string()).and_then(|(a, b)| {
+        let end = b.checked_add(1).ok_or("end is past the last address")?;
+        Ok(a..end)
+    });
+    parsed.unwrap_or_else(|e| {
+        eprintln!("bad range {:?}: {}", arg, e);
+        process::exit(2)
+    })
+}
+
+/// Options shared by the commands that run a program
+struct Setup {
+    fuel: Option<u64>,
+    loops: bool,
+    protection: Option<Protection>,
+    overflow: Overflow,
+}
+
+/// Applies the shared options to a program about to run
+fn configure(program: &mut Program, setup: &Setup) {
+    program.fuel = setup.fuel;
+    program.protection = setup.protection.clone();
+    program.overflow = setup.overflow;
+    if setup.loops {
+        program.loop_detector = Some(LoopDetector::new());
+    }
+}
+
+/// Whether `command` takes the option `flag`
+fn accepts(command: &str, flag: &str) -> bool {
+    let running = [
+        "--fuel",
+        "--loops",
+        "--read-only",
+        "--no-execute",
+        "--overflow",
+    ];
+    let extra: &[&str] = match command {
+        "coverage" => &[],
+        "heatmap" => &["--resume", "--ppm", "--pgm", "--width"],
+        "profile" => &["--resume"],
+        "trace" => &["--resume", "--addresses", "--opcodes"],
+        "run" => &["--resume", "--save", "--blocks"],
+        "selfmod" => &["--resume", "--fault"],
+        "debug" => return flag == "--resume",
+        _ => return false,
+    };
+    running.contains(&flag) || extra.contains(&flag)
+}
+
+/// Parses the input values given after the file name
+fn inputs(args: &[&str]) -> VecDeque<i64> {
+    args.iter()
+        .map(|a| {
+            a.parse::<i64>().unwrap_or_else(|e| {
+                eprintln!("bad input {:?}: {}", a, e);
+                process::exit(2)
+            })
+        })
+        .collect()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let resume = switch(&mut args, "--resume");
+    let loops = switch(&mut args, "--loops");
+    let fault = switch(&mut args, "--fault");
+    let blocks = switch(&mut args, "--blocks");
+    let save = option(&mut args, "--save");
+    let fuel = option(&mut args, "--fuel").map(|n| {
+        n.parse::<u64>().unwrap_or_else(|e| {
+            eprintln!("bad fuel {:?}: {}", n, e);
+            process::exit(2)
+        })
+    });
+    let mut protection = Protection::new();
+    while let Some(arg) = option(&mut args, "--read-only") {
+        protection = protection.read_only(range(arg));
+    }
+    while let Some(arg) = option(&mut args, "--no-execute") {
+        protection = protection.no_execute(range(arg));
+    }
+    let policy = option(&mut args, "--overflow");
+    let overflow = match policy {
+        None | Some("checked") => Overflow::Checked,
+        Some("wrapping") => Overflow::Wrapping,
+        Some("saturating") => Overflow::Saturating,
+        Some(policy) => {
+            eprintln!("bad overflow policy {:?}", policy);
+            process::exit(2)
+        }
+    };
+    let ppm = option(&mut args, "--ppm");
+    let pgm = option(&mut args, "--pgm");
+    let width_arg = option(&mut args, "--width");
+    let width = match width_arg.map(str::parse::<usize>) {
+        None => 64,
+        Some(Ok(width)) if width > 0 => width,
+        Some(_) => {
+            eprintln!("width must be a positive number");
+            process::exit(2)
+        }
+    };
+    let addresses = option(&mut args, "--addresses").map(range);
+    let opcodes = option(&mut args, "--opcodes").map(|mnemonics| {
+        mnemonics
+            .split(',')
+            .map(|m| {
+                Opcode::from_mnemonic(m).unwrap_or_else(|| {
+                    eprintln!("bad opcode {:?}", m);
+                    process::exit(2)
+                })
+            })
+            .collect::<Vec<Opcode>>()
+    });
+    let given = [
+        ("--resume", resume),
+        ("--loops", loops),
+        ("--fault", fault),
+        ("--blocks", blocks),
+        ("--save", save.is_some()),
+        ("--fuel", fuel.is_some()),
+        ("--read-only", !protection.read_only.is_empty()),
+        ("--no-execute", !protection.no_execute.is_empty()),
+        ("--overflow", policy.is_some()),
+        ("--ppm", ppm.is_some()),
+        ("--pgm", pgm.is_some()),
+        ("--width", width_arg.is_some()),
+        ("--addresses", addresses.is_some()),
+        ("--opcodes", opcodes.is_some()),
+    ];
+    if let Some(command) = args.first() {
+        let refused = given
+            .iter()
+            .find(|(flag, set)| *set && !accepts(command, flag));
+        if let Some((flag, _)) = refused {
+            eprintln!("{} does not take {}", command, flag);
+            process::exit(2)
+        }
+    }
+    let setup = Setup {
+        fuel,
+        loops,
+        protection: Some(protection).filter(|p| *p != Protection::new()),
+        overflow,
+    };
+    match args.as_slice() {
+        ["asm", path] => match asm::assemble(&read(path)) {
+            Ok(program) => {
+                let cells: Vec<String> = program.iter().map(|c| c.to_string()).collect();
+                println!("{}", cells.join(","));
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                process::exit(1)
+            }
+        },
+        ["cfg", path] => print!("{}", cfg::recover(&load(path)).dot()),
+        ["coverage", path, input @ ..] => {
+            let mut coverage = Coverage::new();
+            for input in input.split(|a| *a == "--") {
+                let mut program = Program::new(load(path), Vec::new());
+                configure(&mut program, &setup);
+                program.coverage = Some(Coverage::new());
+                match program.run(&mut inputs(input)) {
+                    Ok(state) => eprintln!("{:?}, output {:?}", state, program.output),
+                    Err(e) => eprintln!("{}", e),
+                }
+                coverage.merge(program.coverage.as_ref().unwrap());
+            }
+            print!("{}", coverage.report(&load(path)));
+        }
+        ["debug", path] => {
+            let mut debugger = Debugger::new(start(path, resume));
+            debugger.repl(io::stdin().lock(), io::stdout()).unwrap();
+        }
+        ["disasm", path] => print!("{}", disasm::listing(&load(path))),
+        ["run", path, input @ ..] => {
+            let mut program = start(path, resume);
+            if blocks {
+                program.block_cache = Some(BlockCache::new());
+            }
+            configure(&mut program, &setup);
+            let res = program.run(&mut inputs(input));
+            for value in &program.output {
+                println!("{}", value);
+            }
+            match res {
+                Ok(state) => eprintln!("{:?}", state),
+                Err(e) => eprintln!("{}", e),
+            }
+            if let Some(save) = save {
+                program.output.clear();
+                if let Err(e) = program.save(create(save)) {
+                    eprintln!("{}: {}", save, e);
+                    process::exit(1)
+                }
+            }
+        }
+        ["heatmap", path, input @ ..] => {
+            let mut program = start(path, resume);
+            configure(&mut program, &setup);
+            program.heatmap = Some(Heatmap::new());
+            let res = program.run(&mut inputs(input));
+            let heatmap = program.heatmap.as_ref().unwrap();
+            let written = ppm
+                .map_or(Ok(()), |ppm| heatmap.write_ppm(create(ppm), width))
+                .and_then(|_| pgm.map_or(Ok(()), |pgm| heatmap.write_pgm(create(pgm), width)))
+                .and_then(|_| heatmap.write_csv(io::stdout().lock()));
+            if let Err(e) = written {
+                eprintln!("{}", e);
+                process::exit(1)
+            }
+            match res {
+                Ok(state) => eprintln!("{:?}, output {:?}", state, program.output),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        ["profile", path, input @ ..] => {
+            let mut program = start(path, resume);
+            configure(&mut program, &setup);
+            program.profiler = Some(Profiler::new());
+            let res = program.run(&mut inputs(input));
+            let profiler = program.profiler.as_ref().unwrap();
+            print!("{}", profiler.report(&program.memory, 20));
+            match res {
+                Ok(state) => eprintln!("{:?}, output {:?}", state, program.output),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        ["selfmod", path, input @ ..] => {
+            let mut program = start(path, resume);
+            configure(&mut program, &setup);
+            program.self_mod = Some(SelfModDetector::new().fault(fault));
+            let res = program.run(&mut inputs(input));
+            for write in &program.self_mod.as_ref().unwrap().writes {
+                println!("{}", write);
+            }
+            match res {
+                Ok(state) => eprintln!("{:?}, output {:?}", state, program.output),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        ["transpile", path] => match transpile::transpile(&load(path)) {
+            Ok(source) => print!("{}", source),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                process::exit(1)
+            }
+        },
+        ["trace", path, input @ ..] => {
+            let mut program = start(path, resume);
+            configure(&mut program, &setup);
+            let mut tracer = Tracer::new(BufWriter::new(io::stdout()));
+            if let Some(range) = addresses {
+                tracer = tracer.addresses(range);
+            }
+            if let Some(opcodes) = &opcodes {
+                tracer = tracer.opcodes(opcodes);
+            }
+            program.tracer = Some(tracer);
+            let res = program.run(&mut inputs(input));
+            // Dropping the tracer flushes it before the summary
+            program.tracer = None;
+            match res {
+                Ok(state) => eprintln!("{:?}, output {:?}", state, program.output),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2)
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use crate::intcode::Program;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 
 /// Parses each line to be an i64
@@ -40,21 +40,16 @@ impl HullPaintingRobot {
         }
     }
     fn paint(&mut self, init: Vec<i64>) {
-        let mut input = init.clone();
-        while self.program.next(&mut input) {
-            if self.program.output.len() > 1 {
-                let d = self.program.output.pop().unwrap() as u8;
-                let c = self.program.output.pop().unwrap() as u8;
-                self.squares_painted.insert(self.current_position, c);
-                self.turn(d);
-                input.clear();
-                input.push(
-                    *self
-                        .squares_painted
-                        .get(&self.current_position)
-                        .map_or_else(|| &0, |c| c) as i64,
-                );
-            }
+        let mut input = VecDeque::from(init);
+        while let Some([c, d]) = self.program.run_until_outputs(&mut input).unwrap() {
+            self.squares_painted.insert(self.current_position, c as u8);
+            self.turn(d as u8);
+            input.push_back(
+                *self
+                    .squares_painted
+                    .get(&self.current_position)
+                    .map_or_else(|| &0, |c| c) as i64,
+            );
         }
     }
     fn turn(&mut self, d: u8) {
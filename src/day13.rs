@@ -1,10 +1,10 @@
-use crate::intcode::Program;
+use crate::intcode::{Program, State};
 
 use termion::async_stdin;
 use termion::raw::IntoRawMode;
 use termion::{color, cursor, style};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 //use std::fmt;
 use std::io::{stdout, Read, Write};
 use std::thread;
@@ -23,11 +23,10 @@ struct Game(HashMap<(i32, i32), i32>);
 #[aoc(day13, part1)]
 fn part_one(input: &[i64]) -> usize {
     let mut program = Program::new(input.to_vec(), Vec::new());
-    program.run(&mut vec![0]);
+    let mut input = VecDeque::from(vec![0]);
     let mut game: Game = Game(HashMap::new());
-    while program.output.len() > 2 {
-        let square: Vec<i32> = program.output.drain(..3).map(|x| x as i32).collect();
-        game.0.insert((square[0], square[1]), square[2]);
+    while let Some([x, y, tile]) = program.run_until_outputs(&mut input).unwrap() {
+        game.0.insert((x as i32, y as i32), tile as i32);
     }
     game.0.values().filter(|x| *x == &2).count()
 }
@@ -43,16 +42,13 @@ fn part_two(input: &[i64]) -> i32 {
 
     let mut free_game = input.to_vec();
     free_game[0] = 2;
-    let mut moves = Vec::new();
+    let mut moves = VecDeque::new();
     let mut program = Program::new(free_game, Vec::new());
     let mut game: Game = Game(HashMap::new());
     let mut score = 0;
     let mut ball_x = 0;
     let mut paddle_x = 0;
-    let mut i = 0;
-    let mut j = 0;
     let mut initialized = false;
-    let mut sleep = 0;
     let mut speed = 2;
 
     write!(
@@ -69,15 +65,8 @@ fn part_two(input: &[i64]) -> i32 {
 
     loop {
         stdout.flush().unwrap();
-        i += 1;
-        program.next(&mut moves);
-        if program.output.len() > 2 {
-            let (x, y, tile) = (
-                program.output[0] as i32,
-                program.output[1] as i32,
-                program.output[2] as i32,
-            );
-            program.output.clear();
+        if let Some([x, y, tile]) = program.run_until_outputs(&mut moves).unwrap() {
+            let (x, y, tile) = (x as i32, y as i32, tile as i32);
             let mut _block = std::option::Option::None;
             match (x, y, tile) {
                 (-1, 0, tile) => {
@@ -137,20 +126,19 @@ fn part_two(input: &[i64]) -> i32 {
                 }
             }
         }
+        moves.clear();
         if paddle_x < ball_x {
-            moves.push(1)
+            moves.push_back(1)
         } else if paddle_x > ball_x {
-            moves.push(-1)
+            moves.push_back(-1)
         } else {
-            moves.push(0)
+            moves.push_back(0)
         }
-        if i > 18000 && game.0.values().filter(|x| *x == &2).count() == 0 {
-            j += 1;
-        }
-        if j > 100 {
+        if program.state == State::Halted {
             break;
         }
-        if i > 18000 && !initialized {
+        // The whole board has been drawn once the game first asks for input
+        if program.state == State::NeedInput && !initialized {
             for y in 0..25 {
                 for x in 0..40 {
                     match game.0.get(&(x, y)) {
@@ -192,11 +180,9 @@ fn part_two(input: &[i64]) -> i32 {
             stdout.flush().unwrap();
             initialized = true;
         }
-        if i > 18000 && sleep > speed {
-            thread::sleep(time::Duration::from_millis(1));
-            sleep = 0;
-        } else {
-            sleep += 1;
+        // Pace the game one frame per input request
+        if initialized && program.state == State::NeedInput {
+            thread::sleep(time::Duration::from_millis(100 / speed));
         }
 
         //write!(stdout, "{}{}", termion::clear::All, game);
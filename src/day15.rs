@@ -1,6 +1,6 @@
 use crate::intcode::Program;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 
 /// Parses each line to be an i64
@@ -73,88 +73,86 @@ fn move_player(x: &mut i32, y: &mut i32, d: i64) {
     };
 }
 
+/// Moves the droid makes before the search stops
+const MOVES: usize = 30_002;
+
 #[aoc(day15, part1)]
 fn part_one(input: &[i64]) -> i32 {
     let mut program = Program::new(input.to_vec(), Vec::new());
     let mut map: Map = Map(HashMap::new());
-    let mut moves = Vec::new();
+    let mut moves = VecDeque::new();
     let mut x = 0;
     let mut y = 0;
     let mut direction = 1;
-    let mut i = 0;
     let mut optimal_moves = 0;
     map.0.insert((x, y), (1, true));
-    moves.push(direction);
+    moves.push_back(direction);
     //println!("{:?}", (x, y));
-    loop {
-        program.next(&mut moves);
-        if let Some(status) = program.output.pop() {
-            //println!("status: {}", status);
-            match status {
-                0 => {
-                    //println!("{:?}", (x, y));
-                    //println!("wall");
-                    insert_wall(&mut map, x, y, direction);
-                    direction = turn_left(direction);
-                    moves.push(direction);
-                }
-                1 => {
-                    //println!("{:?}", (x, y));
-                    //println!("open");
-                    if let Some(this) = map.0.get(&(x, y)) {
-                        //println!("this: {:?}", this);
-                        if this.1 {
-                            map.0.insert((x, y), (3, true));
-                        } else {
-                            map.0.insert((x, y), (3, false));
-                        }
-                    } else {
+    for _ in 0..MOVES {
+        let status = match program.run_until_output(&mut moves).unwrap() {
+            Some(status) => status,
+            None => break,
+        };
+        //println!("status: {}", status);
+        match status {
+            0 => {
+                //println!("{:?}", (x, y));
+                //println!("wall");
+                insert_wall(&mut map, x, y, direction);
+                direction = turn_left(direction);
+                moves.push_back(direction);
+            }
+            1 => {
+                //println!("{:?}", (x, y));
+                //println!("open");
+                if let Some(this) = map.0.get(&(x, y)) {
+                    //println!("this: {:?}", this);
+                    if this.1 {
                         map.0.insert((x, y), (3, true));
+                    } else {
+                        map.0.insert((x, y), (3, false));
                     }
-                    move_player(&mut x, &mut y, direction);
-                    let next = map.0.get(&(x, y));
-                    //println!("next: {:?}", next);
-                    match next {
-                        Some((_, true)) => {
-                            direction = turn_right(direction);
-                            moves.push(direction);
-                            optimal_moves -= 1;
-                            map.0.insert((x, y), (1, true));
-                        }
-                        Some((_, false)) => {
-                            direction = turn_right(direction);
-                            moves.push(direction);
-                            optimal_moves += 1;
-                            map.0.insert((x, y), (1, true));
-                        }
-                        None => {
-                            moves.push(direction);
-                            optimal_moves += 1;
-                            map.0.insert((x, y), (1, true));
-                        }
-                    }
+                } else {
+                    map.0.insert((x, y), (3, true));
                 }
-                2 => {
-                    println!("{:?}", (x, y));
-                    println!("oxygen");
-                    move_player(&mut x, &mut y, direction);
-                    map.0.insert((x, y), (2, false));
-                    direction = turn_right(direction);
-                    direction = turn_right(direction);
-                    moves.push(direction);
-                    //println!("{}", map);
-                    //break;
+                move_player(&mut x, &mut y, direction);
+                let next = map.0.get(&(x, y));
+                //println!("next: {:?}", next);
+                match next {
+                    Some((_, true)) => {
+                        direction = turn_right(direction);
+                        moves.push_back(direction);
+                        optimal_moves -= 1;
+                        map.0.insert((x, y), (1, true));
+                    }
+                    Some((_, false)) => {
+                        direction = turn_right(direction);
+                        moves.push_back(direction);
+                        optimal_moves += 1;
+                        map.0.insert((x, y), (1, true));
+                    }
+                    None => {
+                        moves.push_back(direction);
+                        optimal_moves += 1;
+                        map.0.insert((x, y), (1, true));
+                    }
                 }
-                _ => panic!("Invalid Status"),
             }
-            //println!("{}", map);
-            //println!("{}", optimal_moves);
-            if i > 30000 {
-                break;
-            } else {
-                i += 1
+            2 => {
+                println!("{:?}", (x, y));
+                println!("oxygen");
+                move_player(&mut x, &mut y, direction);
+                map.0.insert((x, y), (2, false));
+                direction = turn_right(direction);
+                direction = turn_right(direction);
+                moves.push_back(direction);
+                //println!("{}", map);
+                //break;
             }
+            _ => panic!("Invalid Status"),
         }
+        //println!("{}", map);
+        //println!("{}", optimal_moves);
     }
     println!("{}", map);
     let spaces: Vec<&(i32, i32)> = map
@@ -1,152 +1,1369 @@
 //use std::io;
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::mem;
 
-#[derive(Clone, Debug)]
+pub mod asm;
+pub mod blocks;
+pub mod cfg;
+pub mod coverage;
+pub mod debugger;
+pub mod disasm;
+pub mod heatmap;
+pub mod io;
+pub mod journal;
+pub mod loops;
+pub mod profile;
+pub mod protect;
+pub mod selfmod;
+pub mod snapshot;
+pub mod trace;
+pub mod transpile;
+pub mod watch;
+
+pub use self::blocks::BlockCache;
+pub use self::coverage::Coverage;
+pub use self::heatmap::Heatmap;
+pub use self::io::{InputFn, InputIter, IntcodeInput, IntcodeOutput, OutputFn};
+pub use self::journal::Journal;
+pub use self::loops::LoopDetector;
+pub use self::profile::Profiler;
+pub use self::protect::Protection;
+pub use self::selfmod::SelfModDetector;
+pub use self::trace::Tracer;
+pub use self::watch::{WatchEvent, Watchpoint};
+
+/// Number of cells in one page of memory
+const PAGE_SIZE: usize = 1024;
+/// Pages below this number are kept in a flat table, higher ones in a map
+const DENSE_PAGES: usize = 4096;
+/// Addresses below this get a slot in the flat table of an `AddressMap`,
+/// higher ones an entry in its map
+const DENSE_ADDRESSES: usize = 1 << 20;
+
+type Page = Box<[i64; PAGE_SIZE]>;
+
+/// Sparse, paged memory that grows on demand. Untouched cells read as 0 and
+/// cost nothing until something non-zero is written to their page.
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+    pages: Vec<Option<Page>>,
+    far: HashMap<usize, Page>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory::default()
+    }
+
+    fn page(&self, number: usize) -> Option<&Page> {
+        if number < DENSE_PAGES {
+            self.pages.get(number).and_then(|p| p.as_ref())
+        } else {
+            self.far.get(&number)
+        }
+    }
+
+    fn page_mut(&mut self, number: usize) -> &mut Page {
+        if number < DENSE_PAGES {
+            if number >= self.pages.len() {
+                self.pages.resize_with(number + 1, || None);
+            }
+            self.pages[number].get_or_insert_with(|| Box::new([0; PAGE_SIZE]))
+        } else {
+            self.far
+                .entry(number)
+                .or_insert_with(|| Box::new([0; PAGE_SIZE]))
+        }
+    }
+
+    pub fn get(&self, address: usize) -> i64 {
+        self.page(address / PAGE_SIZE)
+            .map_or(0, |p| p[address % PAGE_SIZE])
+    }
+
+    pub fn set(&mut self, address: usize, value: i64) {
+        if value == 0 && self.page(address / PAGE_SIZE).is_none() {
+            return;
+        }
+        self.page_mut(address / PAGE_SIZE)[address % PAGE_SIZE] = value;
+    }
+
+    /// Copies `len` cells starting at `start`
+    pub fn cells(&self, start: usize, len: usize) -> Vec<i64> {
+        (start..start.saturating_add(len))
+            .map(|a| self.get(a))
+            .collect()
+    }
+
+    /// Allocated pages by number, far ones in no particular order
+    fn allocated(&self) -> impl Iterator<Item = (usize, &Page)> {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter_map(|(n, p)| p.as_ref().map(|p| (n, p)))
+            .chain(self.far.iter().map(|(n, p)| (*n, p)))
+    }
+
+    /// Allocated pages in address order, as (first address, cells)
+    pub fn pages(&self) -> Vec<(usize, &[i64])> {
+        let mut pages: Vec<(usize, &[i64])> = self
+            .allocated()
+            .map(|(n, p)| (n * PAGE_SIZE, &p[..]))
+            .collect();
+        pages.sort_by_key(|p| p.0);
+        pages
+    }
+}
+
+/// Memories are equal when every cell reads the same, whichever pages
+/// happen to be allocated
+impl PartialEq for Memory {
+    fn eq(&self, other: &Memory) -> bool {
+        let covers = |a: &Memory, b: &Memory| {
+            a.allocated().all(|(n, page)| match b.page(n) {
+                Some(other) => page == other,
+                None => page.iter().all(|c| *c == 0),
+            })
+        };
+        covers(self, other) && covers(other, self)
+    }
+}
+
+impl Eq for Memory {}
+
+impl From<Vec<i64>> for Memory {
+    fn from(data: Vec<i64>) -> Self {
+        let mut memory = Memory::new();
+        for (address, value) in data.into_iter().enumerate() {
+            memory.set(address, value);
+        }
+        memory
+    }
+}
+
+/// Something kept per address, such as a count or a flag. Low addresses
+/// index a flat table grown on demand, far ones a map, so a program
+/// jumping to 10^12 costs one entry instead of a huge table. Addresses
+/// never touched hold `T::default()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressMap<T> {
+    dense: Vec<T>,
+    far: BTreeMap<usize, T>,
+}
+
+impl<T: Clone + Default> AddressMap<T> {
+    pub fn new() -> Self {
+        AddressMap {
+            dense: Vec::new(),
+            far: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, address: usize) -> T {
+        match self.dense.get(address) {
+            Some(value) => value.clone(),
+            None => self.far.get(&address).cloned().unwrap_or_default(),
+        }
+    }
+
+    pub fn get_mut(&mut self, address: usize) -> &mut T {
+        if address >= DENSE_ADDRESSES {
+            return self.far.entry(address).or_default();
+        }
+        if address >= self.dense.len() {
+            self.dense.resize(address + 1, T::default());
+        }
+        &mut self.dense[address]
+    }
+
+    /// The flat table, from address 0 up to the highest low address touched
+    pub fn dense(&self) -> &[T] {
+        &self.dense
+    }
+
+    /// Every address touched so far in address order, along with slots of
+    /// the flat table that were never touched
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.dense
+            .iter()
+            .enumerate()
+            .chain(self.far.iter().map(|(a, v)| (*a, v)))
+    }
+}
+
+/// Operation selected by the last two digits of an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Add,
+    Mul,
+    Input,
+    Output,
+    JumpIfTrue,
+    JumpIfFalse,
+    LessThan,
+    Equals,
+    AdjustBase,
+    Halt,
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 10] = [
+        Opcode::Add,
+        Opcode::Mul,
+        Opcode::Input,
+        Opcode::Output,
+        Opcode::JumpIfTrue,
+        Opcode::JumpIfFalse,
+        Opcode::LessThan,
+        Opcode::Equals,
+        Opcode::AdjustBase,
+        Opcode::Halt,
+    ];
+
+    pub fn from_code(code: i64) -> Option<Opcode> {
+        match code {
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Mul),
+            3 => Some(Opcode::Input),
+            4 => Some(Opcode::Output),
+            5 => Some(Opcode::JumpIfTrue),
+            6 => Some(Opcode::JumpIfFalse),
+            7 => Some(Opcode::LessThan),
+            8 => Some(Opcode::Equals),
+            9 => Some(Opcode::AdjustBase),
+            99 => Some(Opcode::Halt),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::AdjustBase => 9,
+            Opcode::Halt => 99,
+        }
+    }
+
+    /// Number of parameters following the instruction cell
+    pub fn arity(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => 3,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
+            Opcode::Input | Opcode::Output | Opcode::AdjustBase => 1,
+            Opcode::Halt => 0,
+        }
+    }
+
+    /// Index of the parameter the instruction writes to, if any
+    pub fn write_param(self) -> Option<usize> {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => Some(2),
+            Opcode::Input => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Number of parameters read as values, as opposed to written
+    pub fn reads(self) -> usize {
+        match self {
+            Opcode::Input | Opcode::Halt => 0,
+            Opcode::Output | Opcode::AdjustBase => 1,
+            _ => 2,
+        }
+    }
+
+    /// Short name used in disassembly
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Mul => "mul",
+            Opcode::Input => "in",
+            Opcode::Output => "out",
+            Opcode::JumpIfTrue => "jt",
+            Opcode::JumpIfFalse => "jf",
+            Opcode::LessThan => "lt",
+            Opcode::Equals => "eq",
+            Opcode::AdjustBase => "arb",
+            Opcode::Halt => "hlt",
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        Opcode::ALL
+            .iter()
+            .copied()
+            .find(|op| op.mnemonic() == mnemonic)
+    }
+}
+
+/// How a parameter is interpreted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    pub fn from_digit(digit: i64) -> Option<Mode> {
+        match digit {
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> i64 {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+}
+
+/// A decoded instruction cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    /// Modes of the parameters, unused ones are `Position`
+    pub modes: [Mode; 3],
+}
+
+impl Instruction {
+    /// Decodes the raw cell found at `pointer`. Mode digits past the
+    /// opcode's arity are ignored, as are digits above the third mode.
+    pub fn decode(pointer: usize, raw: i64) -> Result<Instruction, IntcodeError> {
+        let opcode = Opcode::from_code(raw % 100).filter(|_| raw >= 0).ok_or(
+            IntcodeError::UnknownOpcode {
+                pointer,
+                instruction: raw,
+            },
+        )?;
+        let mut modes = [Mode::Position; 3];
+        let mut digits = raw / 100;
+        for (i, mode) in modes.iter_mut().enumerate().take(opcode.arity()) {
+            *mode = Mode::from_digit(digits % 10).ok_or(IntcodeError::UnsupportedMode {
+                pointer,
+                instruction: raw,
+                mode: digits % 10,
+            })?;
+            if *mode == Mode::Immediate && opcode.write_param() == Some(i) {
+                return Err(IntcodeError::ImmediateWrite {
+                    pointer,
+                    instruction: raw,
+                });
+            }
+            digits /= 10;
+        }
+        Ok(Instruction { opcode, modes })
+    }
+
+    /// Raw cell value for this instruction
+    pub fn encode(&self) -> i64 {
+        self.modes
+            .iter()
+            .rev()
+            .fold(0, |acc, mode| acc * 10 + mode.digit())
+            * 100
+            + self.opcode.code()
+    }
+}
+
+/// Fault raised when the machine hits an instruction it cannot execute
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntcodeError {
+    /// The opcode part of the instruction is not a known operation
+    UnknownOpcode { pointer: usize, instruction: i64 },
+    /// A parameter uses a mode other than position, immediate or relative
+    UnsupportedMode {
+        pointer: usize,
+        instruction: i64,
+        mode: i64,
+    },
+    /// A write parameter is in immediate mode
+    ImmediateWrite { pointer: usize, instruction: i64 },
+    /// An address is negative
+    InvalidAddress {
+        pointer: usize,
+        instruction: i64,
+        address: i64,
+    },
+    /// A relative address or relative base adjustment overflows an i64
+    AddressOverflow { pointer: usize, instruction: i64 },
+    /// A write onto executed code, or an instruction made of written cells,
+    /// while a `SelfModDetector` is set to fault
+    SelfModifying {
+        pointer: usize,
+        instruction: i64,
+        address: usize,
+    },
+    /// A write to an address `Protection` marks read-only
+    WriteProtected {
+        pointer: usize,
+        instruction: i64,
+        address: usize,
+    },
+    /// An instruction covering an address `Protection` marks no-execute
+    ExecuteProtected {
+        pointer: usize,
+        instruction: i64,
+        address: usize,
+    },
+    /// An add or mul result that doesn't fit in an i64, under
+    /// `Overflow::Checked`
+    Overflow { pointer: usize, instruction: i64 },
+}
+
+impl IntcodeError {
+    /// Code pointer of the faulting instruction
+    pub fn pointer(&self) -> usize {
+        match *self {
+            IntcodeError::UnknownOpcode { pointer, .. }
+            | IntcodeError::UnsupportedMode { pointer, .. }
+            | IntcodeError::ImmediateWrite { pointer, .. }
+            | IntcodeError::InvalidAddress { pointer, .. }
+            | IntcodeError::AddressOverflow { pointer, .. }
+            | IntcodeError::SelfModifying { pointer, .. }
+            | IntcodeError::WriteProtected { pointer, .. }
+            | IntcodeError::ExecuteProtected { pointer, .. }
+            | IntcodeError::Overflow { pointer, .. } => pointer,
+        }
+    }
+
+    /// Raw instruction cell at the faulting pointer
+    pub fn instruction(&self) -> i64 {
+        match *self {
+            IntcodeError::UnknownOpcode { instruction, .. }
+            | IntcodeError::UnsupportedMode { instruction, .. }
+            | IntcodeError::ImmediateWrite { instruction, .. }
+            | IntcodeError::InvalidAddress { instruction, .. }
+            | IntcodeError::AddressOverflow { instruction, .. }
+            | IntcodeError::SelfModifying { instruction, .. }
+            | IntcodeError::WriteProtected { instruction, .. }
+            | IntcodeError::ExecuteProtected { instruction, .. }
+            | IntcodeError::Overflow { instruction, .. } => instruction,
+        }
+    }
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "fault at {} (instruction {}): ",
+            self.pointer(),
+            self.instruction()
+        )?;
+        match self {
+            IntcodeError::UnknownOpcode { .. } => write!(f, "unknown opcode"),
+            IntcodeError::UnsupportedMode { mode, .. } => {
+                write!(f, "unsupported parameter mode {}", mode)
+            }
+            IntcodeError::ImmediateWrite { .. } => write!(f, "write in immediate mode"),
+            IntcodeError::InvalidAddress { address, .. } => {
+                write!(f, "address {} out of range", address)
+            }
+            IntcodeError::AddressOverflow { .. } => write!(f, "relative address overflow"),
+            IntcodeError::SelfModifying { address, .. } => {
+                write!(f, "self-modifying code at {}", address)
+            }
+            IntcodeError::WriteProtected { address, .. } => {
+                write!(f, "write to read-only address {}", address)
+            }
+            IntcodeError::ExecuteProtected { address, .. } => {
+                write!(f, "execute of no-execute address {}", address)
+            }
+            IntcodeError::Overflow { .. } => write!(f, "arithmetic overflow"),
+        }
+    }
+}
+
+impl error::Error for IntcodeError {}
+
+/// What `add` and `mul` do when the result doesn't fit in an i64
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Fail with `IntcodeError::Overflow`
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+impl Overflow {
+    pub fn add(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Overflow::Checked => a.checked_add(b),
+            Overflow::Wrapping => Some(a.wrapping_add(b)),
+            Overflow::Saturating => Some(a.saturating_add(b)),
+        }
+    }
+
+    pub fn mul(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Overflow::Checked => a.checked_mul(b),
+            Overflow::Wrapping => Some(a.wrapping_mul(b)),
+            Overflow::Saturating => Some(a.saturating_mul(b)),
+        }
+    }
+}
+
+/// What the machine did on its last step, or why a run stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Executed an instruction and can keep going
+    Running,
+    /// Blocked on an input instruction with no input available
+    NeedInput,
+    /// Produced an output value
+    Output(i64),
+    /// Reached a halt instruction
+    Halted,
+    /// Executed an instruction that fired a stopping watchpoint
+    Watchpoint(WatchEvent),
+    /// Proven to go round `start..end` forever, or to wait for input
+    /// that will never come
+    Looping { start: usize, end: usize },
+    /// Ran out of fuel before the next instruction, with the total number
+    /// of instructions executed so far
+    OutOfFuel(u64),
+}
+
+/// Record of one executed instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Executed {
+    pub pointer: usize,
+    pub instruction: Instruction,
+    /// Values of the read parameters, `opcode.reads()` of them are used
+    pub reads: [i64; 2],
+    /// Addresses behind the read parameters, for position and relative mode
+    pub read_addresses: [Option<usize>; 2],
+    /// Address and value written, if any
+    pub write: Option<(usize, i64)>,
+    /// Relative base after the instruction
+    pub relative_base: i64,
+}
+
+#[derive(Debug)]
 pub struct Program {
-    /// Data of the program (parsed input)
-    pub data: Vec<i64>,
+    /// Memory of the program, loaded with the parsed input
+    pub memory: Memory,
     /// Output from program
     pub output: Vec<i64>,
     /// Code pointer
     pub pointer: usize,
-    pub relative_base: usize,
-    pub waiting: bool,
+    /// Base for relative mode parameters, may go negative between uses
+    pub relative_base: i64,
+    /// Result of the last step
+    pub state: State,
+    /// Optional per-instruction trace, not carried over by `clone`
+    pub tracer: Option<Tracer>,
+    /// Optional undo history for `step_back`
+    pub journal: Option<Journal>,
+    /// Memory watchpoints, not carried over by `clone`
+    pub watchpoints: Vec<Watchpoint>,
+    /// Instructions left to execute before stopping with `OutOfFuel`,
+    /// unlimited if `None`
+    pub fuel: Option<u64>,
+    /// Instructions executed so far
+    pub executed: u64,
+    /// Optional hang detection
+    pub loop_detector: Option<LoopDetector>,
+    /// Optional execution counts
+    pub profiler: Option<Profiler>,
+    /// Optional per-cell access counts
+    pub heatmap: Option<Heatmap>,
+    /// Optional record of executed addresses
+    pub coverage: Option<Coverage>,
+    /// Optional detection of writes onto code
+    pub self_mod: Option<SelfModDetector>,
+    /// Optional read-only and no-execute ranges
+    pub protection: Option<Protection>,
+    /// Decoded blocks, making `run` use the block engine when set
+    pub block_cache: Option<BlockCache>,
+    /// How `add` and `mul` handle results out of range
+    pub overflow: Overflow,
+}
+
+impl Clone for Program {
+    fn clone(&self) -> Self {
+        Program {
+            memory: self.memory.clone(),
+            output: self.output.clone(),
+            pointer: self.pointer,
+            relative_base: self.relative_base,
+            state: self.state,
+            tracer: None,
+            journal: self.journal.clone(),
+            watchpoints: Vec::new(),
+            fuel: self.fuel,
+            executed: self.executed,
+            loop_detector: self.loop_detector.clone(),
+            profiler: self.profiler.clone(),
+            heatmap: self.heatmap.clone(),
+            coverage: self.coverage.clone(),
+            self_mod: self.self_mod.clone(),
+            protection: self.protection.clone(),
+            block_cache: self.block_cache.clone(),
+            overflow: self.overflow,
+        }
+    }
 }
 
 impl Program {
     pub fn new(data: Vec<i64>, output: Vec<i64>) -> Self {
-        let mut extra_memory = vec![0; 1000];
-        let mut p = Program {
-            data,
+        Program {
+            memory: Memory::from(data),
             output,
             pointer: 0,
             relative_base: 0,
-            waiting: false,
-        };
-        p.data.append(&mut extra_memory);
-        p
+            state: State::Running,
+            tracer: None,
+            journal: None,
+            watchpoints: Vec::new(),
+            fuel: None,
+            executed: 0,
+            loop_detector: None,
+            profiler: None,
+            heatmap: None,
+            coverage: None,
+            self_mod: None,
+            protection: None,
+            block_cache: None,
+            overflow: Overflow::default(),
+        }
     }
 
-    pub fn read(&mut self, mode: &u8, ptr: usize) -> i64 {
-        match mode {
-            &0u8 => self.data[self.data[ptr] as usize],
-            &1u8 => self.data[ptr],
-            &2u8 => self.data[(self.data[ptr] + self.relative_base as i64) as usize],
-            _ => panic!("unsupported read mode"),
+    /// Raw instruction cell at the code pointer
+    fn instruction(&self) -> i64 {
+        self.memory.get(self.pointer)
+    }
+
+    /// Whether the last step halted and the pointer is still on the halt
+    fn halted(&self) -> bool {
+        self.state == State::Halted
+            && Instruction::decode(self.pointer, self.instruction()).map(|i| i.opcode)
+                == Ok(Opcode::Halt)
+    }
+
+    fn invalid_address(&self, address: i64) -> IntcodeError {
+        IntcodeError::InvalidAddress {
+            pointer: self.pointer,
+            instruction: self.instruction(),
+            address,
         }
     }
 
-    pub fn write(&mut self, mode: &u8, ptr: usize, content: i64) {
-        let pos;
+    fn cell(&self, address: i64) -> Result<i64, IntcodeError> {
+        if address < 0 {
+            return Err(self.invalid_address(address));
+        }
+        Ok(self.memory.get(address as usize))
+    }
+
+    /// Offsets a parameter by the relative base
+    fn relative(&self, param: i64) -> Result<i64, IntcodeError> {
+        self.relative_base
+            .checked_add(param)
+            .ok_or(IntcodeError::AddressOverflow {
+                pointer: self.pointer,
+                instruction: self.instruction(),
+            })
+    }
+
+    pub fn read(&self, mode: Mode, ptr: usize) -> Result<i64, IntcodeError> {
+        let param = self.memory.get(ptr);
         match mode {
-            &0u8 => pos = self.data[ptr] as usize,
-            &2u8 => pos = (self.data[ptr] + self.relative_base as i64) as usize,
-            _ => panic!("unsupported read mode"),
-        }
-        self.data[pos] = content
-    }
-
-    pub fn run(&mut self, input: &mut Vec<i64>) {
-        while self.next(input) {}
-    }
-
-    // Continues the execution of the program, returning
-    // true if the program should continue, false if it should stop
-    pub fn next(&mut self, input: &mut Vec<i64>) -> bool {
-        let instruction = format!("{:0>6}", &self.data[self.pointer]);
-        let opcode = &instruction[4..6];
-        let mode_a = &instruction[3..4].parse::<u8>().unwrap();
-        let mode_b = &instruction[2..3].parse::<u8>().unwrap();
-        let mode_c = &instruction[1..2].parse::<u8>().unwrap();
-        let res = match opcode {
-            "01" => {
-                let read_a = self.read(mode_a, self.pointer + 1);
-                let read_b = self.read(mode_b, self.pointer + 2);
-                self.write(mode_c, self.pointer + 3, read_a + read_b);
+            Mode::Position => self.cell(param),
+            Mode::Immediate => Ok(param),
+            Mode::Relative => self.cell(self.relative(param)?),
+        }
+    }
+
+    /// Address a position or relative parameter refers to, if valid
+    fn param_address(&self, mode: Mode, ptr: usize) -> Option<usize> {
+        let param = self.memory.get(ptr);
+        let address = match mode {
+            Mode::Position => param,
+            Mode::Immediate => return None,
+            Mode::Relative => self.relative_base.checked_add(param)?,
+        };
+        if address < 0 {
+            return None;
+        }
+        Some(address as usize)
+    }
+
+    /// Resolves the address a write parameter points at
+    fn target(&self, mode: Mode, ptr: usize) -> Result<usize, IntcodeError> {
+        let param = self.memory.get(ptr);
+        let pos = match mode {
+            Mode::Position => param,
+            Mode::Immediate => {
+                return Err(IntcodeError::ImmediateWrite {
+                    pointer: self.pointer,
+                    instruction: self.instruction(),
+                })
+            }
+            Mode::Relative => self.relative(param)?,
+        };
+        if pos < 0 {
+            return Err(self.invalid_address(pos));
+        }
+        Ok(pos as usize)
+    }
+
+    /// Writes `content` where the parameter at `ptr` points, returning the address
+    pub fn write(&mut self, mode: Mode, ptr: usize, content: i64) -> Result<usize, IntcodeError> {
+        let pos = self.target(mode, ptr)?;
+        self.memory.set(pos, content);
+        Ok(pos)
+    }
+
+    /// Adds or multiplies under the overflow policy
+    fn arithmetic(&self, opcode: Opcode, a: i64, b: i64) -> Result<i64, IntcodeError> {
+        let value = match opcode {
+            Opcode::Add => self.overflow.add(a, b),
+            _ => self.overflow.mul(a, b),
+        };
+        value.ok_or(IntcodeError::Overflow {
+            pointer: self.pointer,
+            instruction: self.instruction(),
+        })
+    }
+
+    /// Moves the code pointer to a jump target
+    fn jump(&mut self, target: i64) -> Result<(), IntcodeError> {
+        if target < 0 {
+            return Err(self.invalid_address(target));
+        }
+        self.pointer = target as usize;
+        Ok(())
+    }
+
+    /// Steps until the program halts or blocks on input, returning which.
+    /// Output is collected in `self.output`.
+    pub fn run<I: IntcodeInput>(&mut self, input: &mut I) -> Result<State, IntcodeError> {
+        let mut output = mem::take(&mut self.output);
+        let res = self.run_io(input, &mut output);
+        self.output = output;
+        res
+    }
+
+    /// Like `run`, but sends output to `output` instead of `self.output`
+    pub fn run_io<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<State, IntcodeError>
+    where
+        I: IntcodeInput,
+        O: IntcodeOutput,
+    {
+        if self.halted() {
+            return Ok(State::Halted);
+        }
+        if let Some(mut cache) = self.block_cache.take() {
+            let res = self.run_blocks(&mut cache, input, output);
+            self.block_cache = Some(cache);
+            return res;
+        }
+        loop {
+            match self.step_io(input, output)? {
+                State::Running | State::Output(_) => (),
+                state => return Ok(state),
+            }
+        }
+    }
+
+    /// Runs until one output is ready and returns it, or `None` if the
+    /// program halted or blocked on input first
+    pub fn run_until_output<I: IntcodeInput>(
+        &mut self,
+        input: &mut I,
+    ) -> Result<Option<i64>, IntcodeError> {
+        Ok(self.run_until_outputs(input)?.map(|[value]| value))
+    }
+
+    /// Runs until `N` outputs are ready and returns them in order. If the
+    /// program halts or blocks on input first, returns `None` and leaves
+    /// the partial outputs in `self.output` for the next call.
+    pub fn run_until_outputs<I: IntcodeInput, const N: usize>(
+        &mut self,
+        input: &mut I,
+    ) -> Result<Option<[i64; N]>, IntcodeError> {
+        while self.output.len() < N {
+            match self.step(input)? {
+                State::Running | State::Output(_) => (),
+                _ => return Ok(None),
+            }
+        }
+        let mut values = [0; N];
+        for (value, out) in values.iter_mut().zip(self.output.drain(..N)) {
+            *value = out;
+        }
+        Ok(Some(values))
+    }
+
+    /// Runs until the program blocks on input or halts, returning
+    /// everything it output along the way
+    pub fn run_until_input<I: IntcodeInput>(
+        &mut self,
+        input: &mut I,
+    ) -> Result<Vec<i64>, IntcodeError> {
+        self.run(input)?;
+        Ok(mem::take(&mut self.output))
+    }
+
+    /// Executes one instruction. Blocking on input or halting leaves the
+    /// pointer in place, so stepping again retries the same instruction.
+    pub fn step<I: IntcodeInput>(&mut self, input: &mut I) -> Result<State, IntcodeError> {
+        let mut output = mem::take(&mut self.output);
+        let res = self.step_io(input, &mut output);
+        self.output = output;
+        res
+    }
+
+    /// Like `step`, but sends output to `output` instead of `self.output`
+    pub fn step_io<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<State, IntcodeError>
+    where
+        I: IntcodeInput,
+        O: IntcodeOutput,
+    {
+        // Stepping a halted machine again runs nothing and counts nothing
+        if self.halted() {
+            return Ok(State::Halted);
+        }
+        if self.fuel == Some(0) {
+            self.state = State::OutOfFuel(self.executed);
+            return Ok(self.state);
+        }
+        let pointer = self.pointer;
+        let instruction = Instruction::decode(pointer, self.instruction())?;
+        let [mode_a, mode_b, mode_c] = instruction.modes;
+        let (relative_base, previous) = (self.relative_base, self.state);
+        let watching = !self.watchpoints.is_empty();
+        let recording = self.tracer.is_some()
+            || self.profiler.is_some()
+            || self.heatmap.is_some()
+            || self.coverage.is_some();
+        let checking = self.self_mod.is_some() || self.protection.is_some();
+        // Cells the journal, watchpoints and write checks need, before the
+        // step changes them
+        let overwritten = match instruction.opcode.write_param() {
+            Some(i) if watching || checking || self.journal.is_some() => {
+                let pos = self.param_address(instruction.modes[i], pointer + 1 + i);
+                pos.map(|pos| (pos, self.memory.get(pos)))
+            }
+            _ => None,
+        };
+        let mut read_addresses = [None; 2];
+        if watching || recording {
+            for (i, address) in read_addresses
+                .iter_mut()
+                .enumerate()
+                .take(instruction.opcode.reads())
+            {
+                *address = self.param_address(instruction.modes[i], pointer + 1 + i);
+            }
+        }
+        self.check_protection(
+            pointer,
+            instruction.opcode.arity(),
+            overwritten.map(|o| o.0),
+        )?;
+        self.check_code_write(
+            pointer,
+            instruction.opcode.arity(),
+            overwritten.map(|o| o.0),
+        )?;
+        let mut reads = [0; 2];
+        let mut write = None;
+        let mut state = match instruction.opcode {
+            Opcode::Add => {
+                let read_a = self.read(mode_a, pointer + 1)?;
+                let read_b = self.read(mode_b, pointer + 2)?;
+                let value = self.arithmetic(Opcode::Add, read_a, read_b)?;
+                reads = [read_a, read_b];
+                write = Some((self.write(mode_c, pointer + 3, value)?, value));
                 self.pointer += 4;
-                true
+                State::Running
             }
-            "02" => {
-                let read_a = self.read(mode_a, self.pointer + 1);
-                let read_b = self.read(mode_b, self.pointer + 2);
-                self.write(mode_c, self.pointer + 3, read_a * read_b);
+            Opcode::Mul => {
+                let read_a = self.read(mode_a, pointer + 1)?;
+                let read_b = self.read(mode_b, pointer + 2)?;
+                let value = self.arithmetic(Opcode::Mul, read_a, read_b)?;
+                reads = [read_a, read_b];
+                write = Some((self.write(mode_c, pointer + 3, value)?, value));
                 self.pointer += 4;
-                true
+                State::Running
             }
-            "03" => {
-                /*let mut input = String::new();
-                                 io::stdin().read_line(&mut input).unwrap();
-                let n: i64 = input.trim().parse().unwrap();
-                self.write(mode_a, self.pointer + 1, n);
-                self.pointer += 2;
-                println!("{}", input); */
-                match input.last() {
-                    Some(_) => {
-                        self.write(mode_a, self.pointer + 1, input.pop().unwrap());
+            Opcode::Input => {
+                // Resolve the target first so a fault does not consume input
+                let pos = self.target(mode_a, pointer + 1)?;
+                match input.read() {
+                    Some(n) => {
+                        self.memory.set(pos, n);
+                        write = Some((pos, n));
                         self.pointer += 2;
+                        State::Running
                     }
-                    None => {
-                        self.waiting = true;
-                    }
+                    None => State::NeedInput,
                 }
-                true
             }
-            "04" => {
-                let read_a = self.read(mode_a, self.pointer + 1);
-                self.output.push(read_a);
+            Opcode::Output => {
+                let read_a = self.read(mode_a, pointer + 1)?;
+                reads[0] = read_a;
+                output.write(read_a);
                 self.pointer += 2;
-                true
+                State::Output(read_a)
             }
-            "05" => {
-                let read_a = self.read(mode_a, self.pointer + 1);
-                let read_b = self.read(mode_b, self.pointer + 2);
+            Opcode::JumpIfTrue => {
+                let read_a = self.read(mode_a, pointer + 1)?;
+                let read_b = self.read(mode_b, pointer + 2)?;
+                reads = [read_a, read_b];
                 if read_a != 0 {
-                    self.pointer = read_b as usize
+                    self.jump(read_b)?
                 } else {
                     self.pointer += 3
                 }
-                true
+                State::Running
             }
-            "06" => {
-                let read_a = self.read(mode_a, self.pointer + 1);
-                let read_b = self.read(mode_b, self.pointer + 2);
+            Opcode::JumpIfFalse => {
+                let read_a = self.read(mode_a, pointer + 1)?;
+                let read_b = self.read(mode_b, pointer + 2)?;
+                reads = [read_a, read_b];
                 if read_a == 0 {
-                    self.pointer = read_b as usize
+                    self.jump(read_b)?
                 } else {
                     self.pointer += 3
                 }
-                true
+                State::Running
             }
-            "07" => {
-                let read_a = self.read(mode_a, self.pointer + 1);
-                let read_b = self.read(mode_b, self.pointer + 2);
-                if read_a < read_b {
-                    self.write(mode_c, self.pointer + 3, 1);
-                } else {
-                    self.write(mode_c, self.pointer + 3, 0);
-                }
+            Opcode::LessThan => {
+                let read_a = self.read(mode_a, pointer + 1)?;
+                let read_b = self.read(mode_b, pointer + 2)?;
+                let value = (read_a < read_b) as i64;
+                reads = [read_a, read_b];
+                write = Some((self.write(mode_c, pointer + 3, value)?, value));
                 self.pointer += 4;
-                true
+                State::Running
             }
-            "08" => {
-                let read_a = self.read(mode_a, self.pointer + 1);
-                let read_b = self.read(mode_b, self.pointer + 2);
-                if read_a == read_b {
-                    self.write(mode_c, self.pointer + 3, 1);
-                } else {
-                    self.write(mode_c, self.pointer + 3, 0);
-                }
+            Opcode::Equals => {
+                let read_a = self.read(mode_a, pointer + 1)?;
+                let read_b = self.read(mode_b, pointer + 2)?;
+                let value = (read_a == read_b) as i64;
+                reads = [read_a, read_b];
+                write = Some((self.write(mode_c, pointer + 3, value)?, value));
                 self.pointer += 4;
-                true
+                State::Running
             }
-            "09" => {
-                let read_a = self.read(mode_a, self.pointer + 1);
-                self.relative_base += read_a as usize;
+            Opcode::AdjustBase => {
+                let read_a = self.read(mode_a, pointer + 1)?;
+                reads[0] = read_a;
+                self.relative_base = self.relative(read_a)?;
                 self.pointer += 2;
-                true
+                State::Running
             }
-            "99" => false,
-            _ => panic!("Invalid Op Code"),
+            Opcode::Halt => State::Halted,
         };
-        res
+        if let (Some(cache), Some((address, _))) = (self.block_cache.as_mut(), write) {
+            cache.invalidate(address);
+        }
+        if state != State::NeedInput {
+            self.executed += 1;
+            if let Some(fuel) = self.fuel.as_mut() {
+                *fuel -= 1;
+            }
+            if recording {
+                let executed = Executed {
+                    pointer,
+                    instruction,
+                    reads,
+                    read_addresses,
+                    write,
+                    relative_base: self.relative_base,
+                };
+                if let Some(tracer) = self.tracer.as_mut() {
+                    tracer.record(&executed);
+                }
+                if let Some(profiler) = self.profiler.as_mut() {
+                    profiler.record(&executed);
+                }
+                if let Some(heatmap) = self.heatmap.as_mut() {
+                    heatmap.record(&executed);
+                }
+                if let Some(coverage) = self.coverage.as_mut() {
+                    coverage.record(&executed);
+                }
+            }
+            let arity = instruction.opcode.arity();
+            self.record_code_write(pointer, arity, write, overwritten.map(|o| o.1));
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            if !matches!(state, State::NeedInput | State::Halted) {
+                journal.push(journal::Entry {
+                    pointer,
+                    relative_base,
+                    state: previous,
+                    overwritten: write.and(overwritten),
+                    input: write
+                        .filter(|_| instruction.opcode == Opcode::Input)
+                        .map(|w| w.1),
+                    output: match state {
+                        State::Output(value) => Some(value),
+                        _ => None,
+                    },
+                });
+            }
+        }
+        if watching && state != State::NeedInput {
+            let mut events = Vec::new();
+            for (i, address) in read_addresses.iter().enumerate() {
+                if let Some(address) = *address {
+                    events.push(WatchEvent {
+                        pointer,
+                        address,
+                        access: watch::Access::Read,
+                        old: reads[i],
+                        new: reads[i],
+                    });
+                }
+            }
+            if let (Some((address, new)), Some((_, old))) = (write, overwritten) {
+                events.push(WatchEvent {
+                    pointer,
+                    address,
+                    access: watch::Access::Write,
+                    old,
+                    new,
+                });
+            }
+            if let Some(event) = self.fire_watchpoints(&events) {
+                state = State::Watchpoint(event);
+            }
+        }
+        if let Some(hang) = self.check_loop(pointer, instruction.opcode, state) {
+            state = hang;
+        }
+        self.state = state;
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    // Programs shared by the tests of the submodules
+
+    /// Reads n, outputs n * 2, loops
+    pub const DOUBLER: [i64; 12] = [3, 11, 1002, 11, 2, 11, 4, 11, 1105, 1, 0, 0];
+
+    /// Day 5 example: outputs 999, 1000 or 1001 as the input is below,
+    /// equal to or above 8
+    pub const COMPARE: [i64; 47] = [
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+        1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20,
+        1105, 1, 46, 98, 99,
+    ];
+
+    /// Day 9 example: outputs a copy of itself
+    pub const QUINE: [i64; 16] = [
+        109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+    ];
+
+    /// Writes a halt far out and jumps to it
+    pub const FAR_HALT: [i64; 7] = [1101, 99, 0, 1000000000000, 1105, 1, 1000000000000];
+
+    /// Runs `data` on `input` after `setup` has configured the program,
+    /// returning the program and how the run ended
+    pub fn run_with(
+        data: &[i64],
+        input: &[i64],
+        setup: impl FnOnce(&mut Program),
+    ) -> (Program, Result<State, IntcodeError>) {
+        let mut program = Program::new(data.to_vec(), Vec::new());
+        setup(&mut program);
+        let res = program.run(&mut VecDeque::from(input.to_vec()));
+        (program, res)
+    }
+
+    #[test]
+    fn states() {
+        let mut program = Program::new(vec![3, 9, 4, 9, 99], Vec::new());
+        let mut input = VecDeque::new();
+        assert_eq!(program.run(&mut input), Ok(State::NeedInput));
+        assert_eq!(program.pointer, 0);
+        assert_eq!(program.state, State::NeedInput);
+        input.push_back(7);
+        assert_eq!(program.step(&mut input), Ok(State::Running));
+        assert_eq!(program.step(&mut input), Ok(State::Output(7)));
+        assert_eq!(program.step(&mut input), Ok(State::Halted));
+        assert_eq!(program.step(&mut input), Ok(State::Halted));
+        assert_eq!(program.run(&mut input), Ok(State::Halted));
+        assert_eq!(program.pointer, 4);
+        assert_eq!(program.output, [7]);
+        assert_eq!(program.executed, 3);
+    }
+
+    #[test]
+    fn run_until() {
+        // Reads n, outputs n, n + 1, n + 2 then halts
+        let mut program = Program::new(
+            vec![
+                3, 100, 4, 100, 1001, 100, 1, 100, 4, 100, 101, 1, 100, 100, 4, 100, 99,
+            ],
+            Vec::new(),
+        );
+        let mut input = VecDeque::new();
+        assert_eq!(program.run_until_output(&mut input), Ok(None));
+        assert_eq!(program.state, State::NeedInput);
+        input.push_back(5);
+        assert_eq!(program.run_until_output(&mut input), Ok(Some(5)));
+        assert_eq!(program.run_until_outputs::<_, 3>(&mut input), Ok(None));
+        assert_eq!(program.state, State::Halted);
+        assert_eq!(program.output, [6, 7]);
+        assert_eq!(program.run_until_outputs(&mut input), Ok(Some([6, 7])));
+
+        let mut program = Program::new(vec![104, 1, 104, 2, 3, 0, 104, 3, 99], Vec::new());
+        assert_eq!(program.run_until_input(&mut input), Ok(vec![1, 2]));
+        assert_eq!(program.state, State::NeedInput);
+        input.push_back(0);
+        assert_eq!(program.run_until_input(&mut input), Ok(vec![3]));
+        assert_eq!(program.state, State::Halted);
+    }
+
+    #[test]
+    fn fuel() {
+        let mut program = Program::new(vec![1105, 1, 0], Vec::new());
+        let mut input = VecDeque::new();
+        program.fuel = Some(10);
+        assert_eq!(program.run(&mut input), Ok(State::OutOfFuel(10)));
+        assert_eq!(program.step(&mut input), Ok(State::OutOfFuel(10)));
+        program.fuel = Some(5);
+        assert_eq!(program.run_until_output(&mut input), Ok(None));
+        assert_eq!(program.state, State::OutOfFuel(15));
+
+        // Blocking on input burns no fuel
+        let mut program = Program::new(vec![3, 5, 4, 5, 99, 0], Vec::new());
+        program.fuel = Some(2);
+        assert_eq!(program.run(&mut input), Ok(State::NeedInput));
+        input.push_back(8);
+        assert_eq!(program.run(&mut input), Ok(State::OutOfFuel(2)));
+        assert_eq!(program.output, [8]);
+        assert_eq!(program.executed, 2);
+
+        // Nor does stepping a halted machine
+        let mut program = Program::new(vec![99], Vec::new());
+        program.fuel = Some(3);
+        assert_eq!(program.run(&mut input), Ok(State::Halted));
+        assert_eq!(program.step(&mut input), Ok(State::Halted));
+        assert_eq!(program.fuel, Some(2));
+    }
+
+    #[test]
+    fn overflow() {
+        let mut input = VecDeque::new();
+        let run = |data: Vec<i64>, overflow| {
+            let mut program = Program::new(data, Vec::new());
+            program.overflow = overflow;
+            program
+                .run(&mut VecDeque::new())
+                .map(|_| program.memory.get(5))
+        };
+        let add = vec![1101, i64::MAX, 1, 5, 99, 0];
+        assert_eq!(
+            run(add.clone(), Overflow::Checked),
+            Err(IntcodeError::Overflow {
+                pointer: 0,
+                instruction: 1101
+            })
+        );
+        assert_eq!(run(add.clone(), Overflow::Wrapping), Ok(i64::MIN));
+        assert_eq!(run(add, Overflow::Saturating), Ok(i64::MAX));
+        let mul = vec![1102, i64::MIN, 2, 5, 99, 0];
+        assert_eq!(run(mul.clone(), Overflow::Wrapping), Ok(0));
+        assert_eq!(run(mul, Overflow::Saturating), Ok(i64::MIN));
+
+        // Day 9's big multiply is in range
+        let mut program = Program::new(vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0], Vec::new());
+        assert_eq!(program.run(&mut input), Ok(State::Halted));
+        let mut program = Program::new(vec![4, 3, 1002, 3, 1 << 62, 3, 99], Vec::new());
+        program.overflow = Overflow::Checked;
+        assert_eq!(
+            program.run(&mut input).unwrap_err().to_string(),
+            "fault at 2 (instruction 1002): arithmetic overflow"
+        );
+        assert_eq!(program.output, [3]);
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(
+            Instruction::decode(0, 1002),
+            Ok(Instruction {
+                opcode: Opcode::Mul,
+                modes: [Mode::Position, Mode::Immediate, Mode::Position]
+            })
+        );
+        assert_eq!(Instruction::decode(0, 21107).unwrap().encode(), 21107);
+        assert_eq!(
+            Instruction::decode(0, 204).unwrap().modes[0],
+            Mode::Relative
+        );
+        assert_eq!(Instruction::decode(0, 99).unwrap().opcode, Opcode::Halt);
+        assert!(Instruction::decode(0, -99).is_err());
+        assert!(Instruction::decode(0, 100).is_err());
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut program = Program::new(vec![1101, 1, 2, 5, 42, 0], Vec::new());
+        assert_eq!(
+            program.run(&mut VecDeque::new()),
+            Err(IntcodeError::UnknownOpcode {
+                pointer: 4,
+                instruction: 42
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported_mode() {
+        let mut program = Program::new(vec![304, 0, 99], Vec::new());
+        let err = program.run(&mut VecDeque::new()).unwrap_err();
+        assert_eq!(
+            err,
+            IntcodeError::UnsupportedMode {
+                pointer: 0,
+                instruction: 304,
+                mode: 3
+            }
+        );
+        assert_eq!(err.pointer(), 0);
+        assert_eq!(err.instruction(), 304);
+    }
+
+    #[test]
+    fn immediate_write() {
+        let mut program = Program::new(vec![11101, 1, 2, 3, 99], Vec::new());
+        assert_eq!(
+            program.step(&mut VecDeque::new()),
+            Err(IntcodeError::ImmediateWrite {
+                pointer: 0,
+                instruction: 11101
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_address() {
+        let mut program = Program::new(vec![4, -7, 99], Vec::new());
+        assert_eq!(
+            program.run(&mut VecDeque::new()),
+            Err(IntcodeError::InvalidAddress {
+                pointer: 0,
+                instruction: 4,
+                address: -7
+            })
+        );
+        assert!(program.output.is_empty());
+    }
+
+    #[test]
+    fn negative_relative_base() {
+        // Pushes the base to 5, pops it back to -2, then reads rb+3 and rb+1
+        let mut program = Program::new(vec![109, 5, 109, -7, 204, 3, 204, 1, 99], Vec::new());
+        assert_eq!(
+            program.run(&mut VecDeque::new()),
+            Err(IntcodeError::InvalidAddress {
+                pointer: 6,
+                instruction: 204,
+                address: -1
+            })
+        );
+        assert_eq!(program.relative_base, -2);
+        assert_eq!(program.output, [5]);
+    }
+
+    #[test]
+    fn relative_overflow() {
+        let mut program = Program::new(vec![109, i64::MAX, 109, 1, 99], Vec::new());
+        assert_eq!(
+            program.run(&mut VecDeque::new()),
+            Err(IntcodeError::AddressOverflow {
+                pointer: 2,
+                instruction: 109
+            })
+        );
+        assert_eq!(program.relative_base, i64::MAX);
+    }
+
+    #[test]
+    fn memory_grows_on_demand() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        assert_eq!(memory.get(2), 3);
+        assert_eq!(memory.get(5_000), 0);
+        assert_eq!(memory.get(usize::MAX), 0);
+        memory.set(10_000_000_000, 7);
+        memory.set(PAGE_SIZE * DENSE_PAGES - 1, 8);
+        memory.set(3 * PAGE_SIZE, 0);
+        assert_eq!(memory.get(10_000_000_000), 7);
+        assert_eq!(memory.get(PAGE_SIZE * DENSE_PAGES - 1), 8);
+        assert_eq!(memory.get(3 * PAGE_SIZE), 0);
+        assert!(memory.page(3).is_none());
+
+        // Equality ignores which pages are allocated
+        let mut other = Memory::from(vec![1, 2, 3]);
+        other.set(PAGE_SIZE * DENSE_PAGES - 1, 8);
+        other.set(10_000_000_000, 7);
+        other.set(70_000_000_000, 1);
+        other.set(70_000_000_000, 0);
+        assert_eq!(memory, other);
+        other.set(5, 1);
+        assert_ne!(memory, other);
+    }
+
+    #[test]
+    fn address_map() {
+        let mut map = AddressMap::new();
+        *map.get_mut(3) += 1;
+        *map.get_mut(1_000_000_000_000) += 2;
+        *map.get_mut(DENSE_ADDRESSES) += 3;
+        assert_eq!(map.get(3), 1);
+        assert_eq!(map.get(2), 0);
+        assert_eq!(map.get(1_000_000_000_000), 2);
+        assert_eq!(map.get(usize::MAX), 0);
+        assert_eq!(map.dense(), [0, 0, 0, 1]);
+        let touched: Vec<(usize, u64)> = map
+            .iter()
+            .map(|(a, c)| (a, *c))
+            .filter(|c| c.1 > 0)
+            .collect();
+        assert_eq!(
+            touched,
+            [(3, 1), (DENSE_ADDRESSES, 3), (1_000_000_000_000, 2)]
+        );
+    }
+
+    #[test]
+    fn far_writes() {
+        // Stores 5 at 1,000,000 and echoes it back
+        let mut program = Program::new(
+            vec![1101, 2, 3, 1000000, 4, 1000000, 4, 2000000, 99],
+            Vec::new(),
+        );
+        assert_eq!(program.run(&mut VecDeque::new()), Ok(State::Halted));
+        assert_eq!(program.output, [5, 0]);
     }
 }
@@ -0,0 +1,324 @@
+//! Assembler for a small intcode source format, the same one `disasm`
+//! prints:
+//!
+//! ```text
+//! ; echo input until it reads a zero
+//! loop:   in [value]
+//!         jf [value], #end
+//!         out [value]
+//!         jt #1, #loop
+//! end:    hlt
+//! value:  data 0
+//! ```
+//!
+//! Operands are `[addr]` for position mode, `#value` for immediate mode and
+//! `rb+offset` for relative mode. Addresses, values and offsets can be
+//! numbers, labels or sums of them like `table+2`.
+use super::{Instruction, Mode, Opcode};
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+/// Why a line failed to assemble
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    BadOperand(String),
+    BadLabel(String),
+    OperandCount { expected: usize, found: usize },
+    ImmediateWrite,
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+}
+
+/// Assembly error with the 1-based line it was found on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic {:?}", m),
+            AsmErrorKind::BadOperand(o) => write!(f, "bad operand {:?}", o),
+            AsmErrorKind::BadLabel(l) => write!(f, "bad label {:?}", l),
+            AsmErrorKind::OperandCount { expected, found } => {
+                write!(f, "expected {} operands, found {}", expected, found)
+            }
+            AsmErrorKind::ImmediateWrite => write!(f, "write operand in immediate mode"),
+            AsmErrorKind::DuplicateLabel(l) => write!(f, "label {:?} defined twice", l),
+            AsmErrorKind::UndefinedLabel(l) => write!(f, "undefined label {:?}", l),
+        }
+    }
+}
+
+impl error::Error for AsmError {}
+
+enum Term {
+    Number(i64),
+    Label(String),
+}
+
+/// Sum of signed terms
+struct Expr(Vec<(i64, Term)>);
+
+enum Statement {
+    Instruction(Opcode, Vec<(Mode, Expr)>),
+    Data(Vec<Expr>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(opcode, _) => 1 + opcode.arity(),
+            Statement::Data(values) => values.len(),
+        }
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && s != "rb"
+        }
+        _ => false,
+    }
+}
+
+fn parse_expr(s: &str) -> Option<Expr> {
+    let mut rest = s.trim();
+    // Plain numbers first, so i64::MIN doesn't have to fit unsigned
+    if let Ok(n) = rest.parse::<i64>() {
+        return Some(Expr(vec![(1, Term::Number(n))]));
+    }
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    if let Some(r) = rest.strip_prefix('-') {
+        sign = -1;
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('+') {
+        rest = r;
+    }
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        if let Ok(n) = term.parse::<i64>() {
+            terms.push((sign, Term::Number(n)));
+        } else if is_identifier(term) {
+            terms.push((sign, Term::Label(term.to_string())));
+        } else {
+            return None;
+        }
+        if end == rest.len() {
+            return Some(Expr(terms));
+        }
+        sign = if rest[end..].starts_with('-') { -1 } else { 1 };
+        rest = &rest[end + 1..];
+    }
+}
+
+fn parse_operand(s: &str) -> Option<(Mode, Expr)> {
+    if let Some(inner) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        Some((Mode::Position, parse_expr(inner)?))
+    } else if let Some(value) = s.strip_prefix('#') {
+        Some((Mode::Immediate, parse_expr(value)?))
+    } else if let Some(offset) = s.strip_prefix("rb") {
+        if offset.trim().is_empty() {
+            Some((Mode::Relative, Expr(vec![(1, Term::Number(0))])))
+        } else if offset.trim_start().starts_with(['+', '-']) {
+            Some((Mode::Relative, parse_expr(offset)?))
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+fn parse_statement(s: &str) -> Result<Statement, AsmErrorKind> {
+    let (word, rest) = match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    };
+    let args: Vec<&str> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+    if word == "data" {
+        return args
+            .iter()
+            .map(|a| parse_expr(a).ok_or_else(|| AsmErrorKind::BadOperand(a.to_string())))
+            .collect::<Result<_, _>>()
+            .map(Statement::Data);
+    }
+    let opcode =
+        Opcode::from_mnemonic(word).ok_or_else(|| AsmErrorKind::UnknownMnemonic(word.into()))?;
+    if args.len() != opcode.arity() {
+        return Err(AsmErrorKind::OperandCount {
+            expected: opcode.arity(),
+            found: args.len(),
+        });
+    }
+    let operands: Vec<(Mode, Expr)> = args
+        .iter()
+        .map(|a| parse_operand(a).ok_or_else(|| AsmErrorKind::BadOperand(a.to_string())))
+        .collect::<Result<_, _>>()?;
+    if let Some(i) = opcode.write_param() {
+        if operands[i].0 == Mode::Immediate {
+            return Err(AsmErrorKind::ImmediateWrite);
+        }
+    }
+    Ok(Statement::Instruction(opcode, operands))
+}
+
+/// Assembles `source` into a program `Program::new` can load
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    // First pass: parse statements and give every label an address
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+    for (n, line) in source.lines().enumerate() {
+        let err = |kind| AsmError { line: n + 1, kind };
+        let mut text = line.split(';').next().unwrap().trim();
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_identifier(label) {
+                return Err(err(AsmErrorKind::BadLabel(label.to_string())));
+            }
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(err(AsmErrorKind::DuplicateLabel(label.to_string())));
+            }
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let statement = parse_statement(text).map_err(err)?;
+        address += statement.size();
+        statements.push((n + 1, statement));
+    }
+
+    // Second pass: resolve labels and encode
+    let mut program = Vec::with_capacity(address);
+    for (line, statement) in statements {
+        let eval = |expr: &Expr| {
+            expr.0.iter().try_fold(0i64, |acc, (sign, term)| {
+                let value = match term {
+                    Term::Number(n) => *n,
+                    Term::Label(l) => *labels.get(l).ok_or_else(|| AsmError {
+                        line,
+                        kind: AsmErrorKind::UndefinedLabel(l.clone()),
+                    })? as i64,
+                };
+                Ok(acc.wrapping_add(sign.wrapping_mul(value)))
+            })
+        };
+        match statement {
+            Statement::Instruction(opcode, operands) => {
+                let mut modes = [Mode::Position; 3];
+                for (mode, (m, _)) in modes.iter_mut().zip(operands.iter()) {
+                    *mode = *m;
+                }
+                program.push(Instruction { opcode, modes }.encode());
+                for (_, expr) in &operands {
+                    program.push(eval(expr)?);
+                }
+            }
+            Statement::Data(values) => {
+                for expr in &values {
+                    program.push(eval(expr)?);
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::disasm;
+    use crate::intcode::tests::{COMPARE, QUINE};
+    use crate::intcode::Program;
+
+    use std::collections::VecDeque;
+
+    #[test]
+    fn instructions() {
+        assert_eq!(
+            assemble("mul [4], #3, [4]\nadd #5, #-2, rb-1\narb rb\nhlt").unwrap(),
+            [1002, 4, 3, 4, 21101, 5, -2, -1, 209, 0, 99]
+        );
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let source = "
+            ; echo input until it reads a zero
+            loop:   in [value]
+                    jf [value], #end
+                    out [value]
+                    jt #1, #loop
+            end:    hlt
+            value:  data 0
+            table:  data 1, table+1, end - 2, -3";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program,
+            [3, 11, 1006, 11, 10, 4, 11, 1105, 1, 0, 99, 0, 1, 13, 8, -3]
+        );
+        let mut p = Program::new(program, Vec::new());
+        p.run(&mut VecDeque::from(vec![4, 5, 0, 6])).unwrap();
+        assert_eq!(p.output, [4, 5]);
+    }
+
+    #[test]
+    fn errors() {
+        let err = |src| assemble(src).unwrap_err();
+        assert_eq!(
+            err("hlt\nmov [1], [2]"),
+            AsmError {
+                line: 2,
+                kind: AsmErrorKind::UnknownMnemonic("mov".into())
+            }
+        );
+        assert_eq!(
+            err("add [1], [2]").kind,
+            AsmErrorKind::OperandCount {
+                expected: 3,
+                found: 2
+            }
+        );
+        assert_eq!(err("\n\nin #3").line, 3);
+        assert_eq!(err("in #3").kind, AsmErrorKind::ImmediateWrite);
+        assert_eq!(err("out 5").kind, AsmErrorKind::BadOperand("5".into()));
+        assert_eq!(
+            err("a: hlt\na: hlt").kind,
+            AsmErrorKind::DuplicateLabel("a".into())
+        );
+        assert_eq!(
+            err("jt #1, #nowhere"),
+            AsmError {
+                line: 1,
+                kind: AsmErrorKind::UndefinedLabel("nowhere".into())
+            }
+        );
+        assert_eq!(err("1st: hlt").to_string(), "line 1: bad label \"1st\"");
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let extremes = [104, i64::MIN, 104, i64::MAX, 99];
+        for program in [&COMPARE[..], &QUINE, &extremes].iter() {
+            let source = disasm::source(program);
+            assert_eq!(&assemble(&source).unwrap(), program);
+            assert_eq!(disasm::source(&assemble(&source).unwrap()), source);
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! A faster way to run programs: straight-line code is decoded once into
+//! blocks that end at a jump or halt, and cached by start address. Running
+//! a cached block skips decoding and parameter fetches entirely.
+//!
+//! Writes landing inside a cached block drop it, so self-modifying code
+//! still runs correctly, just without the speedup for the rewritten part.
+//! The interpreter takes over for anything the blocks don't model: fuel
+//! and every optional observer on `Program`.
+use super::{Instruction, IntcodeError, IntcodeInput, IntcodeOutput, Memory, Mode, Opcode};
+use super::{Program, State};
+
+use std::mem;
+
+/// Blocks are only cached below this address, the interpreter runs code
+/// beyond it
+const DENSE_CELLS: usize = 1 << 16;
+
+/// An instruction with its parameters fetched
+#[derive(Clone, Copy, Debug)]
+struct Op {
+    pointer: usize,
+    instruction: Instruction,
+    params: [i64; 3],
+}
+
+#[derive(Clone, Debug)]
+struct Block {
+    ops: Vec<Op>,
+    /// One past the last cell of the block
+    end: usize,
+}
+
+/// Decoded blocks of a program, kept on `Program::block_cache`. Code that
+/// writes to `Program::memory` directly between runs must `invalidate` the
+/// cells it changes, or `clear` the cache.
+#[derive(Clone, Debug, Default)]
+pub struct BlockCache {
+    blocks: Vec<Option<Block>>,
+    /// Number of cached blocks covering each cell
+    covered: Vec<u32>,
+    /// Cells in the longest block cached so far
+    longest: usize,
+    /// Blocks run from the cache
+    pub hits: u64,
+    /// Blocks decoded
+    pub misses: u64,
+    /// Blocks dropped because a write landed inside them
+    pub invalidations: u64,
+}
+
+/// Decodes from `start` up to and including the next jump or halt, or up
+/// to the first cell that doesn't decode
+fn decode(memory: &Memory, start: usize) -> Block {
+    let mut ops = Vec::new();
+    let mut pointer = start;
+    while let Ok(instruction) = Instruction::decode(pointer, memory.get(pointer)) {
+        let arity = instruction.opcode.arity();
+        let mut params = [0; 3];
+        for (i, param) in params.iter_mut().enumerate().take(arity) {
+            *param = memory.get(pointer + 1 + i);
+        }
+        ops.push(Op {
+            pointer,
+            instruction,
+            params,
+        });
+        pointer += 1 + arity;
+        if matches!(
+            instruction.opcode,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse | Opcode::Halt
+        ) {
+            break;
+        }
+    }
+    Block { ops, end: pointer }
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        BlockCache::default()
+    }
+
+    /// Number of blocks currently cached
+    pub fn len(&self) -> usize {
+        self.blocks.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.covered.clear();
+        self.longest = 0;
+    }
+
+    /// Whether any cached block covers `address`
+    pub fn is_code(&self, address: usize) -> bool {
+        self.covered.get(address).is_some_and(|c| *c > 0)
+    }
+
+    fn insert(&mut self, start: usize, block: Block) {
+        if self.blocks.len() <= start {
+            self.blocks.resize(start + 1, None);
+        }
+        if self.covered.len() < block.end {
+            self.covered.resize(block.end, 0);
+        }
+        for count in &mut self.covered[start..block.end] {
+            *count += 1;
+        }
+        self.longest = self.longest.max(block.end - start);
+        self.blocks[start] = Some(block);
+    }
+
+    /// Drops every block covering `address`
+    pub fn invalidate(&mut self, address: usize) {
+        if !self.is_code(address) {
+            return;
+        }
+        let last = address.min(self.blocks.len() - 1);
+        for start in address.saturating_sub(self.longest)..=last {
+            if !matches!(&self.blocks[start], Some(block) if block.end > address) {
+                continue;
+            }
+            let block = self.blocks[start].take().unwrap();
+            for count in &mut self.covered[start..block.end] {
+                *count -= 1;
+            }
+            self.invalidations += 1;
+        }
+    }
+}
+
+impl Program {
+    /// Whether anything needs the interpreter to see each step
+    fn observed(&self) -> bool {
+        self.tracer.is_some()
+            || self.journal.is_some()
+            || !self.watchpoints.is_empty()
+            || self.fuel.is_some()
+            || self.loop_detector.is_some()
+            || self.profiler.is_some()
+            || self.heatmap.is_some()
+            || self.coverage.is_some()
+            || self.self_mod.is_some()
+            || self.protection.is_some()
+    }
+
+    /// Value of a pre-fetched parameter
+    fn operand(&self, mode: Mode, param: i64) -> Result<i64, IntcodeError> {
+        match mode {
+            Mode::Position => self.cell(param),
+            Mode::Immediate => Ok(param),
+            Mode::Relative => self.cell(self.relative(param)?),
+        }
+    }
+
+    /// Address a pre-fetched write parameter points at
+    fn store_address(&self, mode: Mode, param: i64) -> Result<usize, IntcodeError> {
+        let pos = match mode {
+            Mode::Relative => self.relative(param)?,
+            // Decoding already refused immediate writes
+            _ => param,
+        };
+        if pos < 0 {
+            return Err(self.invalid_address(pos));
+        }
+        Ok(pos as usize)
+    }
+
+    /// Runs one instruction through the interpreter, with the cache in
+    /// place so its writes invalidate blocks
+    fn interpret<I, O>(
+        &mut self,
+        cache: &mut BlockCache,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<State, IntcodeError>
+    where
+        I: IntcodeInput,
+        O: IntcodeOutput,
+    {
+        self.block_cache = Some(mem::take(cache));
+        let res = self.step_io(input, output);
+        *cache = self.block_cache.take().unwrap();
+        res
+    }
+
+    /// `run_io` over cached blocks, falling back to the interpreter while
+    /// anything observes the run
+    pub(super) fn run_blocks<I, O>(
+        &mut self,
+        cache: &mut BlockCache,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<State, IntcodeError>
+    where
+        I: IntcodeInput,
+        O: IntcodeOutput,
+    {
+        loop {
+            let start = self.pointer;
+            let cached = cache.blocks.get(start).is_some_and(Option::is_some);
+            if !cached && start < DENSE_CELLS && !self.observed() {
+                let block = decode(&self.memory, start);
+                if !block.ops.is_empty() {
+                    cache.insert(start, block);
+                    cache.misses += 1;
+                }
+            }
+            cache.hits += cached as u64;
+            let block = match cache.blocks.get(start) {
+                Some(Some(block)) if !self.observed() => block,
+                // Far code, bad instructions and observed runs
+                _ => match self.interpret(cache, input, output)? {
+                    State::Running | State::Output(_) => continue,
+                    state => return Ok(state),
+                },
+            };
+            // A write into code, possibly this block, ends the block early
+            let mut dirty = None;
+            for op in &block.ops {
+                self.pointer = op.pointer;
+                let [a, b, c] = op.params;
+                let [mode_a, mode_b, mode_c] = op.instruction.modes;
+                let mut write = None;
+                match op.instruction.opcode {
+                    Opcode::Add => {
+                        let (x, y) = (self.operand(mode_a, a)?, self.operand(mode_b, b)?);
+                        let value = self.arithmetic(Opcode::Add, x, y)?;
+                        write = Some((self.store_address(mode_c, c)?, value));
+                        self.pointer += 4;
+                    }
+                    Opcode::Mul => {
+                        let (x, y) = (self.operand(mode_a, a)?, self.operand(mode_b, b)?);
+                        let value = self.arithmetic(Opcode::Mul, x, y)?;
+                        write = Some((self.store_address(mode_c, c)?, value));
+                        self.pointer += 4;
+                    }
+                    Opcode::Input => {
+                        let pos = self.store_address(mode_a, a)?;
+                        match input.read() {
+                            Some(n) => write = Some((pos, n)),
+                            None => {
+                                self.state = State::NeedInput;
+                                return Ok(self.state);
+                            }
+                        }
+                        self.pointer += 2;
+                    }
+                    Opcode::Output => {
+                        output.write(self.operand(mode_a, a)?);
+                        self.pointer += 2;
+                    }
+                    Opcode::JumpIfTrue => {
+                        if self.operand(mode_a, a)? != 0 {
+                            self.jump(self.operand(mode_b, b)?)?
+                        } else {
+                            self.pointer += 3
+                        }
+                    }
+                    Opcode::JumpIfFalse => {
+                        if self.operand(mode_a, a)? == 0 {
+                            self.jump(self.operand(mode_b, b)?)?
+                        } else {
+                            self.pointer += 3
+                        }
+                    }
+                    Opcode::LessThan => {
+                        let value = (self.operand(mode_a, a)? < self.operand(mode_b, b)?) as i64;
+                        write = Some((self.store_address(mode_c, c)?, value));
+                        self.pointer += 4;
+                    }
+                    Opcode::Equals => {
+                        let value = (self.operand(mode_a, a)? == self.operand(mode_b, b)?) as i64;
+                        write = Some((self.store_address(mode_c, c)?, value));
+                        self.pointer += 4;
+                    }
+                    Opcode::AdjustBase => {
+                        self.relative_base = self.relative(self.operand(mode_a, a)?)?;
+                        self.pointer += 2;
+                    }
+                    Opcode::Halt => {
+                        self.executed += 1;
+                        self.state = State::Halted;
+                        return Ok(self.state);
+                    }
+                }
+                self.executed += 1;
+                if let Some((address, value)) = write {
+                    self.memory.set(address, value);
+                    if cache.is_code(address) {
+                        dirty = Some(address);
+                        break;
+                    }
+                }
+            }
+            self.state = State::Running;
+            if let Some(address) = dirty {
+                cache.invalidate(address);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::{COMPARE, QUINE};
+
+    use std::collections::VecDeque;
+
+    /// Runs `data` on the interpreter and on cached blocks, checking both
+    /// end in the same place, and returns the output
+    fn both(data: &[i64], input: &[i64]) -> Vec<i64> {
+        let mut interpreted = Program::new(data.to_vec(), Vec::new());
+        let mut cached = Program::new(data.to_vec(), Vec::new());
+        cached.block_cache = Some(BlockCache::new());
+        let res = interpreted.run(&mut VecDeque::from(input.to_vec()));
+        assert_eq!(cached.run(&mut VecDeque::from(input.to_vec())), res);
+        assert_eq!(cached.pointer, interpreted.pointer);
+        assert_eq!(cached.relative_base, interpreted.relative_base);
+        assert_eq!(cached.executed, interpreted.executed);
+        assert_eq!(cached.state, interpreted.state);
+        assert_eq!(cached.memory.pages(), interpreted.memory.pages());
+        assert_eq!(cached.output, interpreted.output);
+        cached.output
+    }
+
+    /// Runs amplifiers with the given phases in a feedback loop, on either
+    /// engine, and returns the last thruster signal
+    fn thrust(data: &[i64], phases: &[i64], blocks: bool) -> i64 {
+        let mut amplifiers: Vec<(Program, VecDeque<i64>)> = phases
+            .iter()
+            .map(|phase| {
+                let mut program = Program::new(data.to_vec(), Vec::new());
+                if blocks {
+                    program.block_cache = Some(BlockCache::new());
+                }
+                (program, VecDeque::from(vec![*phase]))
+            })
+            .collect();
+        let mut signal = 0;
+        loop {
+            for (program, input) in amplifiers.iter_mut() {
+                input.push_back(signal);
+                signal = *program.run_until_input(input).unwrap().last().unwrap();
+            }
+            if amplifiers[4].0.state == State::Halted {
+                return signal;
+            }
+        }
+    }
+
+    #[test]
+    fn day_5() {
+        assert_eq!(both(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50], &[]), []);
+        assert_eq!(both(&[3, 0, 4, 0, 99], &[23]), [23]);
+        assert_eq!(both(&[1101, 100, -1, 4, 0], &[]), []);
+        let diagnostics = [
+            3, 28, 1, 28, 6, 6, 1100, 1, 238, 28, 104, 0, 1102, 72, 20, 27, 1001, 27, -1440, 27, 4,
+            27, 102, 99, 7, 26, 1001, 224, 5, 224, 99,
+        ];
+        assert_eq!(both(&diagnostics, &[1]), [0, 0]);
+        assert_eq!(both(&COMPARE, &[5]), [999]);
+        assert_eq!(both(&COMPARE, &[8]), [1000]);
+        assert_eq!(both(&COMPARE, &[23]), [1001]);
+    }
+
+    #[test]
+    fn day_7() {
+        let chains: [(&[i64], [i64; 5], i64); 5] = [
+            (
+                &[
+                    3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+                ],
+                [4, 3, 2, 1, 0],
+                43210,
+            ),
+            (
+                &[
+                    3, 23, 3, 24, 1002, 24, 10, 24, 1002, 23, -1, 23, 101, 5, 23, 23, 1, 24, 23,
+                    23, 4, 23, 99, 0, 0,
+                ],
+                [0, 1, 2, 3, 4],
+                54321,
+            ),
+            (
+                &[
+                    3, 31, 3, 32, 1002, 32, 10, 32, 1001, 31, -2, 31, 1007, 31, 0, 33, 1002, 33, 7,
+                    33, 1, 33, 31, 31, 1, 32, 31, 31, 4, 31, 99, 0, 0, 0,
+                ],
+                [1, 0, 4, 3, 2],
+                65210,
+            ),
+            (
+                &[
+                    3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001,
+                    28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+                ],
+                [9, 8, 7, 6, 5],
+                139629729,
+            ),
+            (
+                &[
+                    3, 52, 1001, 52, -5, 52, 3, 53, 1, 52, 56, 54, 1007, 54, 5, 55, 1005, 55, 26,
+                    1001, 54, -5, 54, 1105, 1, 12, 1, 53, 54, 53, 1008, 54, 0, 55, 1001, 55, 1, 55,
+                    2, 53, 55, 53, 4, 53, 1001, 56, -1, 56, 1005, 56, 6, 99, 0, 0, 0, 0, 10,
+                ],
+                [9, 7, 8, 5, 6],
+                18216,
+            ),
+        ];
+        for (data, phases, signal) in chains.iter() {
+            assert_eq!(thrust(data, phases, false), *signal);
+            assert_eq!(thrust(data, phases, true), *signal);
+            // Each amplifier on its own, stopping for input along the way
+            assert_eq!(both(data, &phases[..1]), []);
+            both(data, &[phases[0], 0]);
+        }
+    }
+
+    #[test]
+    fn day_9() {
+        assert_eq!(both(&[104, 1125899906842624, 99], &[]), [1125899906842624]);
+        assert_eq!(
+            both(&[1102, 34915192, 34915192, 7, 4, 7, 99, 0], &[]),
+            [1219070632396864]
+        );
+        assert_eq!(both(&QUINE, &[]), QUINE);
+    }
+
+    #[test]
+    fn self_modifying() {
+        // Counts down from the input by rewriting the operand of `out #0`
+        let data = [3, 3, 104, 0, 1001, 3, -1, 3, 1005, 3, 2, 99];
+        assert_eq!(both(&data, &[3]), [3, 2, 1]);
+        let mut program = Program::new(data.to_vec(), Vec::new());
+        program.block_cache = Some(BlockCache::new());
+        program.run(&mut VecDeque::from(vec![3])).unwrap();
+        let cache = program.block_cache.unwrap();
+        assert_eq!((cache.misses, cache.hits, cache.invalidations), (6, 2, 4));
+        assert!(!cache.is_code(3));
+
+        // Faults match the interpreter's, pointer and all
+        let faults = [
+            vec![1, -1, 0, 0, 99],
+            vec![1106, 0, -2],
+            vec![7, 0, 0],
+            vec![1101, i64::MAX, 1, 0, 99],
+        ];
+        for data in faults.iter() {
+            let mut interpreted = Program::new(data.clone(), Vec::new());
+            let mut cached = Program::new(data.clone(), Vec::new());
+            cached.block_cache = Some(BlockCache::new());
+            let res = interpreted.run(&mut VecDeque::new());
+            assert!(res.is_err());
+            assert_eq!(cached.run(&mut VecDeque::new()), res);
+            assert_eq!(cached.executed, interpreted.executed);
+        }
+    }
+}
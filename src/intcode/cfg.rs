@@ -0,0 +1,323 @@
+//! Static control-flow graph recovery. Starting at address 0, follows
+//! `jt`/`jf` instructions with immediate targets to split a program into
+//! basic blocks. Jumps through position or relative parameters can't be
+//! followed without running the program and are marked unresolved.
+//!
+//! Subroutine calls push a constant return address before jumping, as in
+//! `add #37, #0, rb+0` then `jf #0, #578`. The return address is treated
+//! as a successor of the call, so code after calls is found too.
+use super::disasm::{self, Line, Op, Operand};
+use super::{Mode, Opcode};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// A jump that is taken
+    Taken,
+    /// A conditional jump falling through
+    NotTaken,
+    /// Straight-line flow into a block some jump also targets
+    Next,
+    /// From a call to where it returns
+    Return,
+}
+
+/// How a block ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    Next,
+    Branch,
+    Halt,
+    /// A jump whose target is only known at run time or lies outside the
+    /// program
+    Unresolved,
+    /// A cell that doesn't decode, or the end of the program
+    Invalid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub lines: Vec<Line>,
+    pub edges: Vec<(usize, Edge)>,
+    pub exit: Exit,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cfg {
+    /// Blocks by start address
+    pub blocks: BTreeMap<usize, Block>,
+}
+
+/// Successors of a jump instruction and whether its target is known
+fn branch(line: &Line, len: usize) -> Option<(Vec<(usize, Edge)>, Exit)> {
+    let (opcode, operands) = match &line.op {
+        Op::Instruction(instruction, operands) => (instruction.opcode, operands),
+        Op::Data(_) => return None,
+    };
+    let jumps_on = match opcode {
+        Opcode::JumpIfTrue => true,
+        Opcode::JumpIfFalse => false,
+        _ => return None,
+    };
+    let (condition, target) = (operands[0], operands[1]);
+    let (taken, falls) = match condition {
+        Operand {
+            mode: Mode::Immediate,
+            value,
+        } => ((value != 0) == jumps_on, (value != 0) != jumps_on),
+        _ => (true, true),
+    };
+    let mut edges = Vec::new();
+    let mut exit = Exit::Branch;
+    if taken {
+        match target {
+            Operand {
+                mode: Mode::Immediate,
+                value,
+            } if value >= 0 && (value as usize) < len => edges.push((value as usize, Edge::Taken)),
+            _ => exit = Exit::Unresolved,
+        }
+    }
+    if falls {
+        edges.push((line.end(), Edge::NotTaken));
+    }
+    Some((edges, exit))
+}
+
+/// The return address a call pushes with an instruction like
+/// `add #37, #0, rb+0`
+fn return_address(line: &Line, len: usize) -> Option<usize> {
+    let (opcode, operands) = match &line.op {
+        Op::Instruction(instruction, operands) => (instruction.opcode, operands),
+        Op::Data(_) => return None,
+    };
+    let (a, b) = match operands[..] {
+        [Operand {
+            mode: Mode::Immediate,
+            value: a,
+        }, Operand {
+            mode: Mode::Immediate,
+            value: b,
+        }, Operand {
+            mode: Mode::Relative,
+            ..
+        }] => (a, b),
+        _ => return None,
+    };
+    let value = match opcode {
+        Opcode::Add => a.checked_add(b)?,
+        Opcode::Mul => a.checked_mul(b)?,
+        _ => return None,
+    };
+    if value < 0 || value as usize >= len {
+        return None;
+    }
+    Some(value as usize)
+}
+
+/// Successors of a jump, plus the return address if the previous line
+/// pushed one
+fn successors(
+    line: &Line,
+    previous: Option<&Line>,
+    len: usize,
+) -> Option<(Vec<(usize, Edge)>, Exit)> {
+    let (mut edges, exit) = branch(line, len)?;
+    if let Some(ret) = previous.and_then(|p| return_address(p, len)) {
+        edges.push((ret, Edge::Return));
+    }
+    Some((edges, exit))
+}
+
+fn is_halt(line: &Line) -> bool {
+    matches!(&line.op, Op::Instruction(i, _) if i.opcode == Opcode::Halt)
+}
+
+/// Splits the code reachable from address 0 into basic blocks
+pub fn recover(program: &[i64]) -> Cfg {
+    // First pass: walk every path to find where blocks start
+    let mut leaders = BTreeSet::new();
+    let mut seen = HashSet::new();
+    let mut work = vec![0];
+    leaders.insert(0);
+    while let Some(start) = work.pop() {
+        let mut address = start;
+        let mut previous = None;
+        while seen.insert(address) {
+            let line = match disasm::decode_at(program, address) {
+                Some(line) => line,
+                None => break,
+            };
+            if let Some((edges, _)) = successors(&line, previous.as_ref(), program.len()) {
+                for (target, _) in edges {
+                    if leaders.insert(target) {
+                        work.push(target);
+                    }
+                }
+                break;
+            }
+            if is_halt(&line) {
+                break;
+            }
+            address = line.end();
+            previous = Some(line);
+        }
+    }
+
+    // Second pass: cut the code at the leaders
+    let mut cfg = Cfg::default();
+    for &start in &leaders {
+        let mut block = Block {
+            start,
+            lines: Vec::new(),
+            edges: Vec::new(),
+            exit: Exit::Invalid,
+        };
+        let mut address = start;
+        loop {
+            if address != start && leaders.contains(&address) {
+                block.edges.push((address, Edge::Next));
+                block.exit = Exit::Next;
+                break;
+            }
+            let line = match disasm::decode_at(program, address) {
+                Some(line) => line,
+                None => {
+                    if let Some(&value) = program.get(address) {
+                        block.lines.push(Line {
+                            address,
+                            cells: vec![value],
+                            op: Op::Data(value),
+                        });
+                    }
+                    break;
+                }
+            };
+            address = line.end();
+            let jump = successors(&line, block.lines.last(), program.len());
+            let halt = is_halt(&line);
+            block.lines.push(line);
+            if let Some((edges, exit)) = jump {
+                block.edges = edges;
+                block.exit = exit;
+                break;
+            }
+            if halt {
+                block.exit = Exit::Halt;
+                break;
+            }
+        }
+        cfg.blocks.insert(start, block);
+    }
+    cfg
+}
+
+impl Cfg {
+    /// Graphviz source with one box per block listing its instructions.
+    /// Taken jumps are solid, fall-through edges dashed, returns from
+    /// calls dotted, and blocks ending
+    /// in an unresolved jump or bad cell are outlined in red.
+    pub fn dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph intcode {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for line in &block.lines {
+                write!(label, "{:>5}: {}\\l", line.address, line.op).unwrap();
+            }
+            match block.exit {
+                Exit::Unresolved => label.push_str("unresolved jump\\l"),
+                Exit::Invalid => label.push_str("invalid code\\l"),
+                _ => (),
+            }
+            let color = match block.exit {
+                Exit::Unresolved | Exit::Invalid => ", color=red",
+                _ => "",
+            };
+            writeln!(out, "    b{} [label=\"{}\"{}];", block.start, label, color).unwrap();
+            for (target, edge) in &block.edges {
+                let style = match edge {
+                    Edge::Taken => "",
+                    Edge::NotTaken | Edge::Next => " [style=dashed]",
+                    Edge::Return => " [style=dotted]",
+                };
+                writeln!(out, "    b{} -> b{}{};", block.start, target, style).unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    fn starts(cfg: &Cfg) -> Vec<usize> {
+        cfg.blocks.keys().copied().collect()
+    }
+
+    #[test]
+    fn blocks_and_edges() {
+        let program = asm::assemble(
+            "
+            start:  in [n]
+            loop:   jf [n], #done
+                    add [n], #-1, [n]
+                    out [n]
+                    jt #1, #loop
+            done:   jt #0, #start
+                    hlt
+            n:      data 0",
+        )
+        .unwrap();
+        let cfg = recover(&program);
+        assert_eq!(starts(&cfg), [0, 2, 5, 14, 17]);
+        let b = |start| &cfg.blocks[&start];
+        assert_eq!(
+            (b(0).exit, &b(0).edges[..]),
+            (Exit::Next, &[(2, Edge::Next)][..])
+        );
+        assert_eq!(b(2).edges, [(14, Edge::Taken), (5, Edge::NotTaken)]);
+        assert_eq!(b(5).lines.len(), 3);
+        assert_eq!(b(5).edges, [(2, Edge::Taken)]);
+        // Never taken, so only falls through
+        assert_eq!(b(14).edges, [(17, Edge::NotTaken)]);
+        assert_eq!(b(17).exit, Exit::Halt);
+    }
+
+    #[test]
+    fn unresolved_and_dot() {
+        // Returns through a relative cell, then runs into data
+        let program = vec![1105, 1, 7, 2106, 0, 0, 99, 1106, 0, 3, 4];
+        let cfg = recover(&program);
+        assert_eq!(starts(&cfg), [0, 3, 7]);
+        assert_eq!(cfg.blocks[&3].exit, Exit::Unresolved);
+        assert_eq!(
+            cfg.dot(),
+            r#"digraph intcode {
+    node [shape=box, fontname="monospace"];
+    b0 [label="    0: jt #1, #7\l"];
+    b0 -> b7;
+    b3 [label="    3: jf #0, rb+0\lunresolved jump\l", color=red];
+    b7 [label="    7: jf #0, #3\l"];
+    b7 -> b3;
+}
+"#
+        );
+        // A call to 9 pushing 7 as its return address
+        let call = recover(&[21101, 7, 0, 0, 1105, 1, 9, 99, 99, 2105, 1, 0]);
+        assert_eq!(starts(&call), [0, 7, 9]);
+        assert_eq!(call.blocks[&0].edges, [(9, Edge::Taken), (7, Edge::Return)]);
+        assert_eq!(call.blocks[&9].exit, Exit::Unresolved);
+
+        let invalid = recover(&[1101, 1, 1, 5, 42]);
+        assert_eq!(invalid.blocks[&0].exit, Exit::Invalid);
+        assert_eq!(invalid.blocks[&0].lines[1].op, Op::Data(42));
+    }
+}
@@ -0,0 +1,143 @@
+use super::disasm::{self, Line, Op};
+use super::{AddressMap, Executed};
+
+use std::fmt::Write;
+
+/// Instruction addresses executed over one or more runs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    hits: AddressMap<bool>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    pub fn record(&mut self, executed: &Executed) {
+        *self.hits.get_mut(executed.pointer) = true;
+    }
+
+    pub fn is_hit(&self, address: usize) -> bool {
+        self.hits.get(address)
+    }
+
+    /// Adds the addresses another run executed
+    pub fn merge(&mut self, other: &Coverage) {
+        for (address, hit) in other.hits.iter() {
+            if *hit {
+                *self.hits.get_mut(address) = true;
+            }
+        }
+    }
+
+    /// Disassembles `program` along the executed addresses. Executed cells
+    /// are always decoded as instructions, and unexecuted ones only when
+    /// they decode cleanly without covering an executed address.
+    pub fn lines(&self, program: &[i64]) -> Vec<(Line, bool)> {
+        let mut lines = Vec::new();
+        let mut address = 0;
+        while address < program.len() {
+            let hit = self.is_hit(address);
+            let line = disasm::decode_at(program, address)
+                .filter(|line| hit || !(address + 1..line.end()).any(|a| self.is_hit(a)))
+                .unwrap_or_else(|| Line {
+                    address,
+                    cells: vec![program[address]],
+                    op: Op::Data(program[address]),
+                });
+            address = line.end();
+            lines.push((line, hit));
+        }
+        lines
+    }
+
+    /// Annotated disassembly marking executed instructions with `+` and
+    /// missed ones with `-`, ending with the share of instructions hit.
+    /// Data cells are unmarked and left out of the count.
+    pub fn report(&self, program: &[i64]) -> String {
+        let mut out = String::new();
+        let (mut hit, mut total) = (0, 0);
+        for (line, executed) in self.lines(program) {
+            let marker = match (&line.op, executed) {
+                (_, true) => '+',
+                (Op::Instruction(..), false) => '-',
+                (Op::Data(_), false) => ' ',
+            };
+            if marker != ' ' {
+                total += 1;
+                hit += executed as usize;
+            }
+            writeln!(out, "{}{}", marker, line).unwrap();
+        }
+        writeln!(
+            out,
+            "coverage {}/{} instructions ({:.1}%)",
+            hit,
+            total,
+            100.0 * hit as f64 / total.max(1) as f64
+        )
+        .unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::{run_with, COMPARE, FAR_HALT};
+
+    fn covered(data: &[i64], input: &[i64]) -> Coverage {
+        let (program, _) = run_with(data, input, |p| p.coverage = Some(Coverage::new()));
+        program.coverage.unwrap()
+    }
+
+    #[test]
+    fn merged_runs() {
+        let mut coverage = covered(&COMPARE, &[3]);
+        assert!(coverage.is_hit(31));
+        assert!(!coverage.is_hit(22));
+        let report = coverage.report(&COMPARE);
+        assert!(report.ends_with("coverage 8/15 instructions (53.3%)\n"));
+        assert!(report.contains("+   31: 104,999                  out #999\n"));
+        assert!(report.contains("-   36: 1101,1000,1,20           add #1000, #1, [20]\n"));
+        assert!(report.contains("    19: 98                       data 98\n"));
+
+        coverage.merge(&covered(&COMPARE, &[8]));
+        coverage.merge(&covered(&COMPARE, &[9]));
+        assert!(coverage
+            .report(&COMPARE)
+            .ends_with("coverage 15/15 instructions (100.0%)\n"));
+    }
+
+    #[test]
+    fn follows_executed_addresses() {
+        // Jumps into the middle of what reads as a three cell instruction
+        let program = [1105, 1, 4, 1105, 104, 7, 99];
+        let mut coverage = Coverage::new();
+        coverage.merge(&covered(&program, &[]));
+        let ops: Vec<String> = coverage
+            .lines(&program)
+            .iter()
+            .map(|(line, hit)| format!("{} {}", hit, line.op))
+            .collect();
+        assert_eq!(
+            ops,
+            [
+                "true jt #1, #4",
+                "false data 1105",
+                "true out #7",
+                "true hlt"
+            ]
+        );
+    }
+
+    #[test]
+    fn far_code() {
+        let mut coverage = covered(&COMPARE, &[8]);
+        coverage.merge(&covered(&FAR_HALT, &[]));
+        assert!(coverage.is_hit(1_000_000_000_000));
+        assert!(coverage.is_hit(4));
+        assert!(!coverage.is_hit(999_999_999_999));
+    }
+}
@@ -0,0 +1,341 @@
+use super::disasm;
+use super::journal::Entry;
+use super::{Journal, Program, State, Watchpoint};
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+
+/// Instructions the debugger can step back over
+const JOURNAL_WINDOW: usize = 100_000;
+/// Most cells one `mem` command dumps
+const MAX_DUMP: usize = 4096;
+
+const HELP: &str = "commands:
+    s, step [n]          execute n instructions (default 1)
+    c, continue          run until a breakpoint, halt, fault or input request
+    k, back [n]          undo n instructions (default 1)
+    rw, rewind <addr>    undo instructions until the pointer is back at addr
+    b, break [addr]      set a breakpoint at addr, or list breakpoints
+    d, delete <addr>     remove the breakpoint at addr
+    wa, watch <addr> [n] stop when n cells from addr are read or written
+    uw, unwatch <addr>   remove watchpoints covering addr
+    r, regs              show pointer, relative base and state
+    l, list [n]          disassemble n instructions from the pointer (default 5)
+    m, mem <addr> [len]  dump len cells starting at addr (default 16, at most 4096)
+    i, input <v>...      queue input values
+    o, output            show and clear pending output
+    w, save <path>       write a snapshot of the program to path
+    h, help              show this help
+    q, quit              leave the debugger";
+
+/// Interactive debugger wrapping a program, its breakpoints and an input queue
+pub struct Debugger {
+    pub program: Program,
+    pub breakpoints: BTreeSet<usize>,
+    pub input: VecDeque<i64>,
+}
+
+impl Debugger {
+    pub fn new(mut program: Program) -> Self {
+        program
+            .journal
+            .get_or_insert_with(|| Journal::new(JOURNAL_WINDOW));
+        Debugger {
+            program,
+            breakpoints: BTreeSet::new(),
+            input: VecDeque::new(),
+        }
+    }
+
+    /// Executes one instruction, describing anything but a plain step
+    fn step<W: Write>(&mut self, out: &mut W) -> io::Result<bool> {
+        match self.program.step(&mut self.input) {
+            Ok(State::Running) | Ok(State::Output(_)) => Ok(true),
+            Ok(State::NeedInput) => {
+                writeln!(out, "waiting for input")?;
+                Ok(false)
+            }
+            Ok(State::Halted) => {
+                writeln!(out, "halted")?;
+                Ok(false)
+            }
+            Ok(State::Watchpoint(event)) => {
+                writeln!(out, "watchpoint: {}", event)?;
+                Ok(false)
+            }
+            Ok(State::Looping { start, end }) => {
+                writeln!(out, "infinite loop in {}..{}", start, end)?;
+                Ok(false)
+            }
+            Ok(State::OutOfFuel(executed)) => {
+                writeln!(out, "out of fuel after {} instructions", executed)?;
+                Ok(false)
+            }
+            Err(e) => {
+                writeln!(out, "{}", e)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Puts input consumed by undone instructions back in the queue
+    fn requeue<W: Write>(&mut self, out: &mut W, undone: Vec<Entry>) -> io::Result<()> {
+        if undone.is_empty() {
+            return writeln!(out, "nothing to undo");
+        }
+        for input in undone.iter().filter_map(|e| e.input) {
+            self.input.push_front(input);
+        }
+        self.show_code(out, 1)
+    }
+
+    fn show_regs<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "pointer {}  relative base {}  state {:?}  pending output {}",
+            self.program.pointer,
+            self.program.relative_base,
+            self.program.state,
+            self.program.output.len()
+        )
+    }
+
+    fn show_code<W: Write>(&self, out: &mut W, count: usize) -> io::Result<()> {
+        let mut address = self.program.pointer;
+        for _ in 0..count {
+            let line = disasm::line_at(&self.program.memory, address);
+            let marker = if self.breakpoints.contains(&address) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(out, "{}{}", marker, line)?;
+            address = line.end();
+        }
+        Ok(())
+    }
+
+    fn show_memory<W: Write>(&self, out: &mut W, start: usize, len: usize) -> io::Result<()> {
+        let cells = self.program.memory.cells(start, len);
+        for (row, chunk) in cells.chunks(8).enumerate() {
+            let values: Vec<String> = chunk.iter().map(|c| format!("{:>8}", c)).collect();
+            writeln!(out, "{:>5}: {}", start + row * 8, values.join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// Runs one command line, returning false when the user quits
+    pub fn command<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        // The only command whose argument isn't a number
+        if let Some(&"w") | Some(&"save") = words.first() {
+            match words.get(1) {
+                Some(path) => {
+                    let saved =
+                        File::create(path).and_then(|f| self.program.save(BufWriter::new(f)));
+                    if let Err(e) = saved {
+                        writeln!(out, "{}: {}", path, e)?;
+                    }
+                }
+                None => writeln!(out, "usage: save <path>")?,
+            }
+            return Ok(true);
+        }
+        let args: Result<Vec<i64>, _> = words.iter().skip(1).map(|w| w.parse()).collect();
+        let args = match args {
+            Ok(args) => args,
+            Err(e) => {
+                writeln!(out, "bad argument: {}", e)?;
+                return Ok(true);
+            }
+        };
+        let arg = |i: usize, default: usize| args.get(i).map_or(default, |&a| a.max(0) as usize);
+        match words.first() {
+            None => (),
+            Some(&"s") | Some(&"step") => {
+                for _ in 0..arg(0, 1) {
+                    if !self.step(out)? {
+                        break;
+                    }
+                }
+                self.show_code(out, 1)?;
+            }
+            Some(&"c") | Some(&"continue") => {
+                // Step before checking so continuing from a breakpoint moves on
+                while self.step(out)? {
+                    if self.breakpoints.contains(&self.program.pointer) {
+                        writeln!(out, "breakpoint at {}", self.program.pointer)?;
+                        break;
+                    }
+                }
+                self.show_code(out, 1)?;
+            }
+            Some(&"k") | Some(&"back") => {
+                let undone = (0..arg(0, 1))
+                    .map_while(|_| self.program.step_back())
+                    .collect();
+                self.requeue(out, undone)?;
+            }
+            Some(&"rw") | Some(&"rewind") => match args.first() {
+                Some(&addr) if addr >= 0 => {
+                    let undone = self.program.run_back_to(addr as usize);
+                    self.requeue(out, undone)?;
+                }
+                _ => writeln!(out, "usage: rewind <addr>")?,
+            },
+            Some(&"b") | Some(&"break") => match args.first() {
+                Some(&addr) if addr >= 0 => {
+                    self.breakpoints.insert(addr as usize);
+                }
+                Some(_) => writeln!(out, "bad address")?,
+                None => {
+                    for b in &self.breakpoints {
+                        writeln!(out, "{}", disasm::line_at(&self.program.memory, *b))?;
+                    }
+                }
+            },
+            Some(&"d") | Some(&"delete") => match args.first() {
+                Some(&addr) if addr >= 0 && self.breakpoints.remove(&(addr as usize)) => (),
+                _ => writeln!(out, "no such breakpoint")?,
+            },
+            Some(&"wa") | Some(&"watch") => match args.first() {
+                Some(&addr) if addr >= 0 => {
+                    let start = addr as usize;
+                    let watchpoint = Watchpoint::new(start..start + arg(1, 1)).reads(true);
+                    self.program.watchpoints.push(watchpoint);
+                }
+                Some(_) => writeln!(out, "bad address")?,
+                None => {
+                    for w in &self.program.watchpoints {
+                        writeln!(out, "{:?}", w.addresses)?;
+                    }
+                }
+            },
+            Some(&"uw") | Some(&"unwatch") => match args.first() {
+                Some(&addr) if addr >= 0 => self
+                    .program
+                    .watchpoints
+                    .retain(|w| !w.addresses.contains(&(addr as usize))),
+                _ => writeln!(out, "usage: unwatch <addr>")?,
+            },
+            Some(&"r") | Some(&"regs") => self.show_regs(out)?,
+            Some(&"l") | Some(&"list") => self.show_code(out, arg(0, 5))?,
+            Some(&"m") | Some(&"mem") => match args.first() {
+                Some(_) if arg(1, 16) > MAX_DUMP => {
+                    writeln!(out, "can dump at most {} cells at once", MAX_DUMP)?
+                }
+                Some(&addr) if addr >= 0 => self.show_memory(out, addr as usize, arg(1, 16))?,
+                _ => writeln!(out, "usage: mem <addr> [len]")?,
+            },
+            Some(&"i") | Some(&"input") => {
+                self.input.extend(args.iter());
+                writeln!(out, "queued input: {:?}", self.input)?;
+            }
+            Some(&"o") | Some(&"output") => {
+                writeln!(out, "{:?}", self.program.output)?;
+                self.program.output.clear();
+            }
+            Some(&"h") | Some(&"help") => writeln!(out, "{}", HELP)?,
+            Some(&"q") | Some(&"quit") => return Ok(false),
+            Some(other) => writeln!(out, "unknown command {:?}, try help", other)?,
+        }
+        Ok(true)
+    }
+
+    /// Reads commands until `quit` or the end of `input`
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        self.show_code(&mut out, 1)?;
+        write!(out, "(icdb) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            if !self.command(&line?, &mut out)? {
+                break;
+            }
+            write!(out, "(icdb) ")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::DOUBLER;
+
+    fn session(program: &[i64], script: &str) -> (Debugger, String) {
+        let mut debugger = Debugger::new(Program::new(program.to_vec(), Vec::new()));
+        let mut out = Vec::new();
+        debugger.repl(script.as_bytes(), &mut out).unwrap();
+        (debugger, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn breakpoints_and_input() {
+        let (debugger, out) = session(&DOUBLER, "b 6\nc\ni 21\nc\nr\nc\no\nc\nq\n");
+        assert!(out.contains("waiting for input"));
+        assert!(out.contains("breakpoint at 6"));
+        assert!(out.contains("pointer 6  relative base 0"));
+        assert!(out.contains("[42]"));
+        assert_eq!(debugger.program.pointer, 0);
+        assert_eq!(debugger.program.state, State::NeedInput);
+    }
+
+    #[test]
+    fn stepping_and_memory() {
+        let program = [1101, 2, 3, 7, 109, -4, 99, 0];
+        let script = "s 2\nm 4 4\nm 0 1000000000000\nl 2\ns\ns\nbogus\n";
+        let (debugger, out) = session(&program, script);
+        assert_eq!(debugger.program.relative_base, -4);
+        assert!(out.contains("    4:      109       -4       99        5"));
+        assert!(out.contains("can dump at most 4096 cells at once"));
+        assert!(out.contains("    6: 99                       hlt"));
+        assert!(out.contains("halted"));
+        assert!(out.contains("unknown command \"bogus\""));
+    }
+
+    #[test]
+    fn stepping_back() {
+        let (debugger, out) = session(&DOUBLER, "i 1 2\nc\nrw 2\nk 2\nrw 0\nk\n");
+        assert!(out.contains("nothing to undo"));
+        assert_eq!(debugger.program.pointer, 0);
+        assert_eq!(debugger.program.memory.get(11), 0);
+        assert_eq!(debugger.input, [1, 2]);
+
+        let (_, out) = session(&DOUBLER, "i 1\nc\nk 2\nc\no\n");
+        assert!(out.contains("[2]\n"));
+        assert!(!out.contains("[2, 2]"));
+    }
+
+    #[test]
+    fn watchpoints() {
+        let (debugger, out) = session(&DOUBLER, "wa 11\ni 4\nc\nc\nwa\nuw 11\nc\n");
+        assert!(out.contains("watchpoint: write [11] 0 -> 4 at 0"));
+        assert!(out.contains("watchpoint: read [11] = 4 at 2"));
+        assert!(out.contains("11..12"));
+        assert!(debugger.program.watchpoints.is_empty());
+        assert_eq!(debugger.program.output, [8]);
+    }
+
+    #[test]
+    fn save_snapshot() {
+        let path = std::env::temp_dir().join(format!("icdb-{}.snapshot", std::process::id()));
+        let script = format!("s 2\nsave {}\nsave\n", path.display());
+        let (debugger, out) = session(&[1101, 2, 3, 7, 109, -4, 99, 0], &script);
+        assert!(out.contains("usage: save <path>"));
+        let saved = Program::load(io::BufReader::new(File::open(&path).unwrap())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved.pointer, debugger.program.pointer);
+        assert_eq!(saved.relative_base, -4);
+        assert_eq!(saved.memory.get(7), 5);
+    }
+
+    #[test]
+    fn faults() {
+        let (debugger, out) = session(&[4, -1], "c\nd 3\n");
+        assert!(out.contains("fault at 0 (instruction 4): address -1 out of range"));
+        assert!(out.contains("no such breakpoint"));
+        assert_eq!(debugger.program.pointer, 0);
+    }
+}
@@ -0,0 +1,216 @@
+use super::{Instruction, Memory, Mode};
+
+use std::fmt;
+
+/// A parameter as written in the listing: `[12]` for position mode,
+/// `#5` for immediate mode and `rb+3` for relative mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Operand {
+    pub mode: Mode,
+    pub value: i64,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immediate => write!(f, "#{}", self.value),
+            Mode::Relative => write!(f, "rb{:+}", self.value),
+        }
+    }
+}
+
+/// What a line of the listing decodes to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    Instruction(Instruction, Vec<Operand>),
+    /// A cell that does not decode to a whole instruction
+    Data(i64),
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Instruction(instruction, operands) => {
+                write!(f, "{}", instruction.opcode.mnemonic())?;
+                for (i, operand) in operands.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}", sep, operand)?;
+                }
+                Ok(())
+            }
+            Op::Data(value) => write!(f, "data {}", value),
+        }
+    }
+}
+
+/// One instruction or data cell of a disassembled program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub address: usize,
+    /// Raw cells the line covers
+    pub cells: Vec<i64>,
+    pub op: Op,
+}
+
+impl Line {
+    /// Address of the line after this one
+    pub fn end(&self) -> usize {
+        self.address + self.cells.len()
+    }
+
+    /// Whether the instruction cell has mode digits the VM ignores, so it
+    /// doesn't re-encode to itself
+    pub fn has_stray_digits(&self) -> bool {
+        matches!(&self.op, Op::Instruction(i, _) if i.encode() != self.cells[0])
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cells: Vec<String> = self.cells.iter().map(|c| c.to_string()).collect();
+        write!(
+            f,
+            "{:>5}: {:<24} {}",
+            self.address,
+            cells.join(","),
+            self.op
+        )?;
+        if self.has_stray_digits() {
+            write!(f, " ; stray mode digits")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the instruction starting at `address`. Returns `None` unless the
+/// cell decodes the way the VM would run it and all its parameters fit in
+/// `memory`.
+pub fn decode_at(memory: &[i64], address: usize) -> Option<Line> {
+    let raw = *memory.get(address)?;
+    let instruction = Instruction::decode(address, raw).ok()?;
+    let arity = instruction.opcode.arity();
+    if address + arity >= memory.len() {
+        return None;
+    }
+    let cells = memory[address..=address + arity].to_vec();
+    let operands = cells[1..]
+        .iter()
+        .zip(instruction.modes.iter())
+        .map(|(&value, &mode)| Operand { mode, value })
+        .collect();
+    Some(Line {
+        address,
+        cells,
+        op: Op::Instruction(instruction, operands),
+    })
+}
+
+/// Decodes the instruction at `address` of a running program's memory,
+/// or a data line if it doesn't hold one
+pub fn line_at(memory: &Memory, address: usize) -> Line {
+    let cells = memory.cells(address, 4);
+    match decode_at(&cells, 0) {
+        Some(line) => Line { address, ..line },
+        None => Line {
+            address,
+            cells: vec![cells[0]],
+            op: Op::Data(cells[0]),
+        },
+    }
+}
+
+/// Decodes `memory` front to back. Cells that don't decode become data
+/// and decoding resumes at the next cell.
+pub fn disassemble(memory: &[i64]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = 0;
+    while address < memory.len() {
+        let line = decode_at(memory, address).unwrap_or_else(|| Line {
+            address,
+            cells: vec![memory[address]],
+            op: Op::Data(memory[address]),
+        });
+        address = line.end();
+        lines.push(line);
+    }
+    lines
+}
+
+/// Full listing with addresses, raw cells and decoded operations
+pub fn listing(memory: &[i64]) -> String {
+    disassemble(memory)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Just the decoded operations, one per line, in the syntax `asm` reads.
+/// Instructions with stray mode digits can't be written that way, so they
+/// become data with the operation in a comment.
+pub fn source(memory: &[i64]) -> String {
+    disassemble(memory)
+        .iter()
+        .map(|line| {
+            if !line.has_stray_digits() {
+                return format!("{}\n", line.op);
+            }
+            let cells: Vec<String> = line.cells.iter().map(|c| c.to_string()).collect();
+            format!("data {} ; {}\n", cells.join(", "), line.op)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    #[test]
+    fn operands() {
+        let lines = disassemble(&[1002, 4, 3, 4, 21101, 5, -2, -1, 209, 0, 99]);
+        let ops: Vec<String> = lines.iter().map(|l| l.op.to_string()).collect();
+        assert_eq!(
+            ops,
+            ["mul [4], #3, [4]", "add #5, #-2, rb-1", "arb rb+0", "hlt"]
+        );
+        assert_eq!(lines[1].address, 4);
+        assert_eq!(lines[1].cells, [21101, 5, -2, -1]);
+    }
+
+    #[test]
+    fn data_cells() {
+        // Immediate writes and truncated instructions fall back to data
+        let lines = disassemble(&[11101, 1, 2, 3, 42, 1001, 1]);
+        let ops: Vec<String> = lines.iter().map(|l| l.op.to_string()).collect();
+        assert_eq!(
+            ops,
+            ["data 11101", "add [2], [3], [42]", "data 1001", "data 1"]
+        );
+    }
+
+    #[test]
+    fn stray_digits() {
+        // The VM ignores mode digits past an opcode's parameters
+        let program = [10099, 11104, 7];
+        assert_eq!(
+            listing(&program),
+            "    0: 10099                    hlt ; stray mode digits
+    1: 11104,7                  out #7 ; stray mode digits
+"
+        );
+        assert_eq!(
+            source(&program),
+            "data 10099 ; hlt\ndata 11104, 7 ; out #7\n"
+        );
+        assert_eq!(&asm::assemble(&source(&program)).unwrap(), &program);
+    }
+
+    #[test]
+    fn listing_format() {
+        assert_eq!(
+            listing(&[104, 7, 99]),
+            "    0: 104,7                    out #7\n    2: 99                       hlt\n"
+        );
+    }
+}
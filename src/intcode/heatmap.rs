@@ -0,0 +1,193 @@
+use super::{AddressMap, Executed};
+
+use std::io::{self, Write};
+
+/// How often one cell was accessed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub reads: u64,
+    pub writes: u64,
+    /// Times the cell was fetched as part of an instruction
+    pub executes: u64,
+}
+
+/// Per-cell access counts of a run, exported as CSV or as an image with
+/// one pixel per cell. Images only cover the flat part of the table, far
+/// cells are left out of them.
+#[derive(Clone, Debug, Default)]
+pub struct Heatmap {
+    cells: AddressMap<Counts>,
+}
+
+/// Scales a count to a pixel value, logarithmically so rarely touched
+/// cells still show up next to hot loops
+fn shade(count: u64, max: u64) -> u8 {
+    if count == 0 {
+        return 0;
+    }
+    let scaled = (count as f64).ln_1p() / (max as f64).ln_1p();
+    (63.0 + 192.0 * scaled).round() as u8
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Heatmap::default()
+    }
+
+    pub fn get(&self, address: usize) -> Counts {
+        self.cells.get(address)
+    }
+
+    pub fn record(&mut self, executed: &Executed) {
+        let end = executed.pointer + executed.instruction.opcode.arity();
+        for address in executed.pointer..=end {
+            self.cells.get_mut(address).executes += 1;
+        }
+        for address in executed.read_addresses.iter().flatten() {
+            self.cells.get_mut(*address).reads += 1;
+        }
+        if let Some((address, _)) = executed.write {
+            self.cells.get_mut(address).writes += 1;
+        }
+    }
+
+    /// Every accessed cell in address order
+    pub fn accessed(&self) -> impl Iterator<Item = (usize, Counts)> + '_ {
+        self.cells
+            .iter()
+            .map(|(address, c)| (address, *c))
+            .filter(|(_, c)| *c != Counts::default())
+    }
+
+    /// `address,reads,writes,executes` for every accessed cell
+    pub fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "address,reads,writes,executes")?;
+        for (address, c) in self.accessed() {
+            writeln!(out, "{},{},{},{}", address, c.reads, c.writes, c.executes)?;
+        }
+        out.flush()
+    }
+
+    /// Binary PPM with `width` cells per row: reads in red, writes in
+    /// green and executes in blue. Fails with `InvalidInput` if `width` is 0.
+    pub fn write_ppm<W: Write>(&self, mut out: W, width: usize) -> io::Result<()> {
+        let rows = self.rows(width)?;
+        let max = self
+            .cells
+            .dense()
+            .iter()
+            .fold(Counts::default(), |m, c| Counts {
+                reads: m.reads.max(c.reads),
+                writes: m.writes.max(c.writes),
+                executes: m.executes.max(c.executes),
+            });
+        let pixels = self.pixels(width, rows, |c| {
+            vec![
+                shade(c.reads, max.reads),
+                shade(c.writes, max.writes),
+                shade(c.executes, max.executes),
+            ]
+        });
+        writeln!(out, "P6\n{} {}\n255", width, rows)?;
+        out.write_all(&pixels)?;
+        out.flush()
+    }
+
+    /// Binary PGM with `width` cells per row, shaded by total accesses.
+    /// Fails with `InvalidInput` if `width` is 0.
+    pub fn write_pgm<W: Write>(&self, mut out: W, width: usize) -> io::Result<()> {
+        let rows = self.rows(width)?;
+        let total = |c: &Counts| c.reads + c.writes + c.executes;
+        let max = self.cells.dense().iter().map(total).max().unwrap_or(0);
+        let pixels = self.pixels(width, rows, |c| vec![shade(total(c), max)]);
+        writeln!(out, "P5\n{} {}\n255", width, rows)?;
+        out.write_all(&pixels)?;
+        out.flush()
+    }
+
+    fn rows(&self, width: usize) -> io::Result<usize> {
+        if width == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image width must be positive",
+            ));
+        }
+        Ok(self.cells.dense().len().div_ceil(width).max(1))
+    }
+
+    /// Pixel data for `rows` whole rows, padding past the last cell with
+    /// black
+    fn pixels(&self, width: usize, rows: usize, pixel: impl Fn(&Counts) -> Vec<u8>) -> Vec<u8> {
+        let blank = Counts::default();
+        (0..rows * width)
+            .flat_map(|address| pixel(self.cells.dense().get(address).unwrap_or(&blank)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::Program;
+
+    use std::collections::VecDeque;
+
+    fn heatmap() -> Heatmap {
+        // Counts [14] down from the input, with a far write on the way out
+        let mut program = Program::new(
+            vec![
+                3, 14, 1001, 14, -1, 14, 1005, 14, 2, 21101, 7, 0, 5000000, 99, 0,
+            ],
+            Vec::new(),
+        );
+        program.heatmap = Some(Heatmap::new());
+        program.run(&mut VecDeque::from(vec![2])).unwrap();
+        program.heatmap.unwrap()
+    }
+
+    #[test]
+    fn counts() {
+        let heatmap = heatmap();
+        assert_eq!(
+            heatmap.get(14),
+            Counts {
+                reads: 4,
+                writes: 3,
+                executes: 0
+            }
+        );
+        assert_eq!(heatmap.get(2).executes, 2);
+        assert_eq!(heatmap.get(13).executes, 1);
+        assert_eq!(heatmap.get(5_000_000).writes, 1);
+
+        let mut csv = Vec::new();
+        heatmap.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("address,reads,writes,executes\n0,0,0,1\n1,0,0,1\n"));
+        assert!(csv.ends_with("\n13,0,0,1\n14,4,3,0\n5000000,0,1,0\n"));
+    }
+
+    #[test]
+    fn images() {
+        let heatmap = heatmap();
+        let mut ppm = Vec::new();
+        heatmap.write_ppm(&mut ppm, 8).unwrap();
+        let header = b"P6\n8 2\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        let pixels = &ppm[header.len()..];
+        assert_eq!(pixels.len(), 8 * 2 * 3);
+        // Cell 14 is the only one read and written, cell 2 the hottest code
+        assert_eq!(&pixels[42..45], &[255, 255, 0]);
+        assert_eq!(&pixels[6..9], &[0, 0, 255]);
+        assert_eq!(&pixels[45..48], &[0, 0, 0]);
+
+        let mut pgm = Vec::new();
+        heatmap.write_pgm(&mut pgm, 16).unwrap();
+        assert_eq!(&pgm[..11], b"P5\n16 1\n255");
+        assert_eq!(pgm.len(), 12 + 16);
+
+        let err = heatmap.write_pgm(Vec::new(), 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(heatmap.write_ppm(Vec::new(), 0).is_err());
+    }
+}
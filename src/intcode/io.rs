@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender};
+
+/// Source of values for input instructions
+pub trait IntcodeInput {
+    /// Next input value, or `None` if there is nothing to read yet
+    fn read(&mut self) -> Option<i64>;
+}
+
+/// Sink for values produced by output instructions
+pub trait IntcodeOutput {
+    fn write(&mut self, value: i64);
+}
+
+impl<T: IntcodeInput + ?Sized> IntcodeInput for &mut T {
+    fn read(&mut self) -> Option<i64> {
+        (**self).read()
+    }
+}
+
+impl<T: IntcodeOutput + ?Sized> IntcodeOutput for &mut T {
+    fn write(&mut self, value: i64) {
+        (**self).write(value)
+    }
+}
+
+/// Values are read in the order they were pushed to the back
+impl IntcodeInput for VecDeque<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl IntcodeOutput for VecDeque<i64> {
+    fn write(&mut self, value: i64) {
+        self.push_back(value)
+    }
+}
+
+impl IntcodeOutput for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value)
+    }
+}
+
+/// Blocks until a value arrives, needs input once every sender is gone
+impl IntcodeInput for Receiver<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Values sent after the receiver hung up are dropped
+impl IntcodeOutput for Sender<i64> {
+    fn write(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+/// Input from a closure, called each time the program reads
+pub struct InputFn<F>(pub F);
+
+impl<F: FnMut() -> Option<i64>> IntcodeInput for InputFn<F> {
+    fn read(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+/// Output to a closure, called with each value the program writes
+pub struct OutputFn<F>(pub F);
+
+impl<F: FnMut(i64)> IntcodeOutput for OutputFn<F> {
+    fn write(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+/// Input from an iterator, needs input once it is exhausted
+pub struct InputIter<I>(pub I);
+
+impl<I: Iterator<Item = i64>> IntcodeInput for InputIter<I> {
+    fn read(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::{Program, State};
+
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Echoes every input until it runs out
+    fn echo() -> Program {
+        Program::new(vec![3, 7, 4, 7, 1105, 1, 0, 0], Vec::new())
+    }
+
+    #[test]
+    fn fifo_queue() {
+        let mut program = echo();
+        let mut input = VecDeque::from(vec![1, 2, 3]);
+        let mut output = VecDeque::new();
+        assert_eq!(
+            program.run_io(&mut input, &mut output),
+            Ok(State::NeedInput)
+        );
+        assert_eq!(output, [1, 2, 3]);
+    }
+
+    #[test]
+    fn closures() {
+        let mut n = 0;
+        let mut seen = Vec::new();
+        let mut program = echo();
+        let state = program.run_io(
+            &mut InputFn(|| {
+                n += 1;
+                if n < 4 {
+                    Some(n * 10)
+                } else {
+                    None
+                }
+            }),
+            &mut OutputFn(|v| seen.push(v)),
+        );
+        assert_eq!(state, Ok(State::NeedInput));
+        assert_eq!(seen, [10, 20, 30]);
+    }
+
+    #[test]
+    fn iterators() {
+        let mut program = echo();
+        program.run(&mut InputIter(5..8)).unwrap();
+        assert_eq!(program.output, [5, 6, 7]);
+    }
+
+    #[test]
+    fn channels() {
+        let (in_tx, mut in_rx) = mpsc::channel();
+        let (mut out_tx, out_rx) = mpsc::channel();
+        let handle = thread::spawn(move || echo().run_io(&mut in_rx, &mut out_tx));
+        for i in 0..3 {
+            in_tx.send(i).unwrap();
+            assert_eq!(out_rx.recv(), Ok(i));
+        }
+        drop(in_tx);
+        assert_eq!(handle.join().unwrap(), Ok(State::NeedInput));
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Program, State};
+
+use std::collections::VecDeque;
+
+/// What one executed instruction changed, enough to undo it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Code pointer before the instruction
+    pub pointer: usize,
+    /// Relative base before the instruction
+    pub relative_base: i64,
+    /// State before the instruction
+    pub state: State,
+    /// Address written and the value it held before
+    pub overwritten: Option<(usize, i64)>,
+    /// Input value consumed
+    pub input: Option<i64>,
+    /// Output value produced
+    pub output: Option<i64>,
+}
+
+/// Undo history of the last `window` instructions. Older entries are
+/// dropped, so stepping back can only go that far.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    window: usize,
+}
+
+impl Journal {
+    pub fn new(window: usize) -> Self {
+        Journal {
+            entries: VecDeque::new(),
+            window,
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        if self.window == 0 {
+            return;
+        }
+        if self.entries.len() == self.window {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+}
+
+impl Program {
+    /// Undoes the last journaled instruction, restoring memory, pointer,
+    /// relative base and state. Output still pending in `self.output` is
+    /// taken back, input is not: the returned entry says what was consumed.
+    pub fn step_back(&mut self) -> Option<Entry> {
+        let entry = self.journal.as_mut()?.pop()?;
+        if let Some((address, value)) = entry.overwritten {
+            self.memory.set(address, value);
+        }
+        if entry.output.is_some() && self.output.last() == entry.output.as_ref() {
+            self.output.pop();
+        }
+        self.pointer = entry.pointer;
+        self.relative_base = entry.relative_base;
+        self.state = entry.state;
+        Some(entry)
+    }
+
+    /// Steps back at least once, until the pointer reaches `address` or the
+    /// journal runs out. Returns the undone entries, most recent first.
+    pub fn run_back_to(&mut self, address: usize) -> Vec<Entry> {
+        let mut undone = Vec::new();
+        while let Some(entry) = self.step_back() {
+            undone.push(entry);
+            if self.pointer == address {
+                break;
+            }
+        }
+        undone
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::{run_with, DOUBLER};
+
+    #[test]
+    fn step_back() {
+        let data = [109, 3, 21101, 2, 3, 4, 104, 9, 99, 0];
+        let (mut program, _) = run_with(&data, &[], |p| p.journal = Some(Journal::new(100)));
+        assert_eq!(program.memory.get(7), 5);
+        assert_eq!(program.output, [5]);
+
+        let entry = program.step_back().unwrap();
+        assert_eq!(entry.output, Some(5));
+        assert!(program.output.is_empty());
+        assert_eq!(program.pointer, 6);
+        assert_eq!(program.state, State::Running);
+        program.step_back();
+        assert_eq!(program.memory.get(7), 9);
+        assert_eq!(program.pointer, 2);
+        program.step_back();
+        assert_eq!(program.relative_base, 0);
+        assert_eq!(program.pointer, 0);
+        assert_eq!(program.step_back(), None);
+
+        // Replaying gives the same result
+        program.run(&mut VecDeque::new()).unwrap();
+        assert_eq!(program.output, [5]);
+    }
+
+    #[test]
+    fn run_back_to() {
+        let (mut program, _) = run_with(&DOUBLER, &[1, 2, 3], |p| {
+            p.journal = Some(Journal::new(100))
+        });
+        assert_eq!(program.output, [2, 4, 6]);
+
+        let undone = program.run_back_to(6);
+        assert_eq!(undone.len(), 2);
+        assert_eq!(undone[0].pointer, 8);
+        assert_eq!(program.memory.get(11), 6);
+        let undone = program.run_back_to(0);
+        let inputs: Vec<i64> = undone.iter().filter_map(|e| e.input).collect();
+        assert_eq!(inputs, [3]);
+        assert_eq!(program.memory.get(11), 4);
+        assert_eq!(program.output, [2, 4]);
+        assert_eq!(program.run_back_to(42).len(), 8);
+        assert_eq!(program.memory.get(11), 0);
+    }
+
+    #[test]
+    fn replay_output() {
+        let (mut program, _) = run_with(&DOUBLER, &[1], |p| p.journal = Some(Journal::new(100)));
+        assert_eq!(program.run_back_to(6).len(), 2);
+        assert!(program.output.is_empty());
+        program.run(&mut VecDeque::new()).unwrap();
+        assert_eq!(program.output, [2]);
+
+        // Output already taken is left alone
+        program.output.clear();
+        program.run_back_to(6);
+        program.run(&mut VecDeque::new()).unwrap();
+        assert_eq!(program.output, [2]);
+    }
+
+    #[test]
+    fn window() {
+        let (mut program, _) = run_with(&DOUBLER, &[1, 2], |p| p.journal = Some(Journal::new(3)));
+        assert_eq!(program.journal.as_ref().unwrap().len(), 3);
+        assert_eq!(program.run_back_to(0).len(), 3);
+        assert_eq!(program.pointer, 2);
+
+        let (mut program, _) = run_with(&DOUBLER, &[1], |p| p.journal = Some(Journal::new(0)));
+        assert_eq!(program.step_back(), None);
+    }
+}
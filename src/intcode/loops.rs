@@ -0,0 +1,157 @@
+use super::{Memory, Opcode, Program, State};
+
+/// Proves a program hangs by spotting a repeated machine state. The state
+/// is checked at every backward jump, and since the machine is deterministic
+/// a repeat with no I/O in between means it will go round forever.
+///
+/// Each checkpoint is compared exactly against one saved state, which is
+/// replaced at doubling intervals as in Brent's cycle detection. A loop is
+/// found within a few laps of entering it, and only one copy of memory is
+/// ever kept.
+#[derive(Clone, Debug)]
+pub struct LoopDetector {
+    /// Treat blocking on input as a hang, for runs given all their input
+    /// up front
+    pub blocked_input: bool,
+    /// Pointer, relative base and memory at the saved checkpoint
+    saved: Option<(usize, i64, Memory)>,
+    /// Checkpoints since the state was saved
+    since_saved: u64,
+    /// Checkpoints to go before saving a new state
+    interval: u64,
+    /// Lowest and highest address executed since the state was saved
+    span: Option<(usize, usize)>,
+}
+
+impl Default for LoopDetector {
+    fn default() -> Self {
+        LoopDetector {
+            blocked_input: true,
+            saved: None,
+            since_saved: 0,
+            interval: 1,
+            span: None,
+        }
+    }
+}
+
+impl LoopDetector {
+    pub fn new() -> Self {
+        LoopDetector::default()
+    }
+
+    fn reset(&mut self) {
+        *self = LoopDetector {
+            blocked_input: self.blocked_input,
+            ..LoopDetector::default()
+        };
+    }
+
+    fn extend(&mut self, start: usize, end: usize) {
+        self.span = Some(match self.span {
+            Some((lo, hi)) => (lo.min(start), hi.max(end)),
+            None => (start, end),
+        });
+    }
+}
+
+impl Program {
+    /// Feeds a finished step to the loop detector, returning a
+    /// `State::Looping` if it proved the program hangs
+    pub(super) fn check_loop(
+        &mut self,
+        pointer: usize,
+        opcode: Opcode,
+        state: State,
+    ) -> Option<State> {
+        let detector = self.loop_detector.as_mut()?;
+        match state {
+            State::NeedInput if detector.blocked_input => {
+                return Some(State::Looping {
+                    start: pointer,
+                    end: pointer + 2,
+                })
+            }
+            State::NeedInput | State::Halted => return None,
+            State::Output(_) => {
+                detector.reset();
+                return None;
+            }
+            _ if opcode == Opcode::Input => {
+                detector.reset();
+                return None;
+            }
+            _ => (),
+        }
+        detector.extend(pointer, pointer + 1 + opcode.arity());
+        let jumped = matches!(opcode, Opcode::JumpIfTrue | Opcode::JumpIfFalse);
+        if !jumped || self.pointer > pointer {
+            return None;
+        }
+        if let Some((pointer, relative_base, memory)) = &detector.saved {
+            if *pointer == self.pointer
+                && *relative_base == self.relative_base
+                && *memory == self.memory
+            {
+                let (start, end) = detector.span?;
+                return Some(State::Looping { start, end });
+            }
+        }
+        detector.since_saved += 1;
+        if detector.since_saved == detector.interval {
+            detector.saved = Some((self.pointer, self.relative_base, self.memory.clone()));
+            detector.since_saved = 0;
+            detector.interval *= 2;
+            detector.span = None;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::run_with;
+
+    fn detect(data: &[i64], input: &[i64]) -> State {
+        let (_, res) = run_with(data, input, |p| {
+            p.loop_detector = Some(LoopDetector::new());
+            p.fuel = Some(10_000);
+        });
+        res.unwrap()
+    }
+
+    #[test]
+    fn proven_loops() {
+        assert_eq!(
+            detect(&[1105, 1, 0], &[]),
+            State::Looping { start: 0, end: 3 }
+        );
+        // Counts 1, 2, 3 in [20], resetting to 0 and going round again
+        let cycle = [
+            1001, 20, 1, 20, 1008, 20, 3, 21, 1006, 21, 0, 1101, 0, 0, 20, 1105, 1, 0,
+        ];
+        assert_eq!(detect(&cycle, &[]), State::Looping { start: 0, end: 18 });
+        // Counts [10] down from 500 before spinning on the last jump
+        let countdown = [1001, 10, -1, 10, 1005, 10, 0, 1105, 1, 7, 500];
+        assert_eq!(
+            detect(&countdown, &[]),
+            State::Looping { start: 7, end: 10 }
+        );
+    }
+
+    #[test]
+    fn terminating_and_io() {
+        // Counts [10] down to zero
+        let countdown = [1001, 10, -1, 10, 1005, 10, 0, 99, 0, 0, 500];
+        assert_eq!(detect(&countdown, &[]), State::Halted);
+        // Echoes forever, output resets the detector
+        assert_eq!(detect(&[104, 1, 1105, 1, 0], &[]), State::OutOfFuel(10_000));
+        // Waits for input that never comes
+        assert_eq!(
+            detect(&[3, 5, 4, 5, 99, 0], &[]),
+            State::Looping { start: 0, end: 2 }
+        );
+        assert_eq!(detect(&[3, 5, 4, 5, 99, 0], &[7]), State::Halted);
+    }
+}
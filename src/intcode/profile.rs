@@ -0,0 +1,157 @@
+use super::disasm;
+use super::{AddressMap, Executed, Memory, Opcode};
+
+use std::cmp::Reverse;
+use std::fmt::Write;
+
+/// Execution counts gathered while a program runs
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    /// Executions of the instruction at each address
+    by_address: AddressMap<u64>,
+    /// Executions of each opcode, in `Opcode::ALL` order
+    pub by_opcode: [u64; 10],
+    pub instructions: u64,
+    pub inputs: u64,
+    pub outputs: u64,
+    /// Highest address executed, read or written
+    pub highest_address: usize,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    pub fn record(&mut self, executed: &Executed) {
+        let opcode = executed.instruction.opcode;
+        let pointer = executed.pointer;
+        *self.by_address.get_mut(pointer) += 1;
+        let index = Opcode::ALL.iter().position(|o| *o == opcode).unwrap();
+        self.by_opcode[index] += 1;
+        self.instructions += 1;
+        match opcode {
+            Opcode::Input => self.inputs += 1,
+            Opcode::Output => self.outputs += 1,
+            _ => (),
+        }
+        let touched = executed
+            .read_addresses
+            .iter()
+            .flatten()
+            .chain(executed.write.as_ref().map(|(address, _)| address))
+            .copied()
+            .chain(Some(executed.pointer + opcode.arity()));
+        for address in touched {
+            self.highest_address = self.highest_address.max(address);
+        }
+    }
+
+    /// Executions of the instruction at `address`
+    pub fn count(&self, address: usize) -> u64 {
+        self.by_address.get(address)
+    }
+
+    /// Addresses by execution count, most executed first
+    pub fn hotspots(&self) -> Vec<(usize, u64)> {
+        let mut hot: Vec<(usize, u64)> = self
+            .by_address
+            .iter()
+            .map(|(address, count)| (address, *count))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot
+    }
+
+    /// Totals, opcode counts and the `top` hottest addresses, disassembled
+    /// from `memory`
+    pub fn report(&self, memory: &Memory, top: usize) -> String {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let mut out = String::new();
+        writeln!(
+            out,
+            "instructions {}  inputs {}  outputs {}  highest address {}",
+            self.instructions, self.inputs, self.outputs, self.highest_address
+        )
+        .unwrap();
+        writeln!(out, "\nopcodes:").unwrap();
+        let mut opcodes: Vec<(Opcode, u64)> = Opcode::ALL
+            .iter()
+            .copied()
+            .zip(self.by_opcode.iter().copied())
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        opcodes.sort_by_key(|o| Reverse(o.1));
+        for (opcode, count) in opcodes {
+            writeln!(
+                out,
+                "    {:<3} {:>12} {:>6.2}%",
+                opcode.mnemonic(),
+                count,
+                percent(count)
+            )
+            .unwrap();
+        }
+        writeln!(out, "\nhot addresses:").unwrap();
+        for (address, count) in self.hotspots().into_iter().take(top) {
+            let line = disasm::line_at(memory, address);
+            writeln!(out, "{:>12} {:>6.2}% {}", count, percent(count), line).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::{run_with, FAR_HALT};
+    use crate::intcode::{Program, State};
+
+    use std::collections::VecDeque;
+
+    #[test]
+    fn counts() {
+        // Counts [20] down from the input, outputting each value
+        let mut program = Program::new(
+            vec![3, 20, 4, 20, 1001, 20, -1, 20, 1005, 20, 2, 99],
+            Vec::new(),
+        );
+        program.profiler = Some(Profiler::new());
+        program.run(&mut VecDeque::from(vec![3])).unwrap();
+        let profiler = program.profiler.as_ref().unwrap();
+        assert_eq!(profiler.instructions, 11);
+        assert_eq!((profiler.inputs, profiler.outputs), (1, 3));
+        assert_eq!(profiler.highest_address, 20);
+        assert_eq!(
+            profiler.hotspots(),
+            [(2, 3), (4, 3), (8, 3), (0, 1), (11, 1)]
+        );
+        assert_eq!(profiler.by_opcode[0], 3);
+        assert_eq!(profiler.count(8), 3);
+
+        let report = profiler.report(&program.memory, 2);
+        assert!(report.starts_with("instructions 11  inputs 1  outputs 3  highest address 20\n"));
+        assert!(report.contains("    add            3  27.27%\n"));
+        assert!(report.contains("    hlt            1   9.09%\n"));
+        assert!(report.ends_with(
+            "hot addresses:
+           3  27.27%     2: 4,20                     out [20]
+           3  27.27%     4: 1001,20,-1,20            add [20], #-1, [20]
+"
+        ));
+    }
+
+    #[test]
+    fn far_code() {
+        let (program, res) = run_with(&FAR_HALT, &[], |p| p.profiler = Some(Profiler::new()));
+        assert_eq!(res, Ok(State::Halted));
+        let profiler = program.profiler.unwrap();
+        assert_eq!(profiler.count(1_000_000_000_000), 1);
+        assert_eq!(
+            profiler.hotspots(),
+            [(0, 1), (4, 1), (1_000_000_000_000, 1)]
+        );
+        assert_eq!(profiler.highest_address, 1_000_000_000_000);
+    }
+}
@@ -0,0 +1,133 @@
+use super::{IntcodeError, Program};
+
+use std::ops::Range;
+
+/// Address ranges a program may not write to or execute from. Breaking
+/// either rule faults with `IntcodeError::WriteProtected` or
+/// `IntcodeError::ExecuteProtected` before the instruction runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Protection {
+    pub read_only: Vec<Range<usize>>,
+    pub no_execute: Vec<Range<usize>>,
+}
+
+impl Protection {
+    pub fn new() -> Self {
+        Protection::default()
+    }
+
+    /// Forbids writes to `addresses`
+    pub fn read_only(mut self, addresses: Range<usize>) -> Self {
+        self.read_only.push(addresses);
+        self
+    }
+
+    /// Forbids executing instructions that cover any of `addresses`
+    pub fn no_execute(mut self, addresses: Range<usize>) -> Self {
+        self.no_execute.push(addresses);
+        self
+    }
+
+    pub fn is_writable(&self, address: usize) -> bool {
+        !self.read_only.iter().any(|r| r.contains(&address))
+    }
+
+    pub fn is_executable(&self, address: usize) -> bool {
+        !self.no_execute.iter().any(|r| r.contains(&address))
+    }
+}
+
+impl Program {
+    /// Fails before the instruction at `pointer` runs if any of its cells
+    /// are no-execute or `target` is read-only
+    pub(super) fn check_protection(
+        &self,
+        pointer: usize,
+        arity: usize,
+        target: Option<usize>,
+    ) -> Result<(), IntcodeError> {
+        let protection = match &self.protection {
+            Some(protection) => protection,
+            None => return Ok(()),
+        };
+        let instruction = self.memory.get(pointer);
+        if let Some(address) = (pointer..=pointer + arity).find(|a| !protection.is_executable(*a)) {
+            return Err(IntcodeError::ExecuteProtected {
+                pointer,
+                instruction,
+                address,
+            });
+        }
+        match target {
+            Some(address) if !protection.is_writable(address) => {
+                Err(IntcodeError::WriteProtected {
+                    pointer,
+                    instruction,
+                    address,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::run_with;
+    use crate::intcode::State;
+
+    #[test]
+    fn read_only() {
+        // Adds one to the input, storing it over the add's own operand
+        let data = [3, 7, 1001, 7, 1, 3, 99, 0];
+        let (program, res) = run_with(&data, &[5], |p| {
+            p.protection = Some(Protection::new().read_only(0..6))
+        });
+        assert_eq!(
+            res,
+            Err(IntcodeError::WriteProtected {
+                pointer: 2,
+                instruction: 1001,
+                address: 3
+            })
+        );
+        assert_eq!(program.memory.get(3), 7);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "fault at 2 (instruction 1001): write to read-only address 3"
+        );
+        // Reading protected cells is fine
+        let (_, res) = run_with(&data, &[5], |p| {
+            p.protection = Some(Protection::new().read_only(0..3))
+        });
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn no_execute() {
+        // Jumps into the data after the halt
+        let data = [1105, 1, 4, 99, 104, 7, 99];
+        let (program, res) = run_with(&data, &[], |p| {
+            p.protection = Some(Protection::new().no_execute(5..7))
+        });
+        assert_eq!(
+            res,
+            Err(IntcodeError::ExecuteProtected {
+                pointer: 4,
+                instruction: 104,
+                address: 5
+            })
+        );
+        assert_eq!(program.output, []);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "fault at 4 (instruction 104): execute of no-execute address 5"
+        );
+        let (program, res) = run_with(&data, &[], |p| {
+            p.protection = Some(Protection::new().no_execute(7..100))
+        });
+        assert_eq!(res, Ok(State::Halted));
+        assert_eq!(program.output, [7]);
+    }
+}
@@ -0,0 +1,230 @@
+use super::{AddressMap, IntcodeError, Program};
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A write onto a cell that was, or later is, executed as part of an
+/// instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodeWrite {
+    /// Pointer of the writing instruction
+    pub writer: usize,
+    pub address: usize,
+    pub old: i64,
+    pub new: i64,
+    /// Whether the cell had already been executed when it was written
+    pub after_execution: bool,
+}
+
+impl fmt::Display for CodeWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>6}  [{}] {} -> {}  {}",
+            self.writer,
+            self.address,
+            self.old,
+            self.new,
+            if self.after_execution {
+                "already executed"
+            } else {
+                "executed later"
+            }
+        )
+    }
+}
+
+/// Tracks executed cells to catch programs rewriting their own code
+#[derive(Clone, Debug, Default)]
+pub struct SelfModDetector {
+    /// Fail with `IntcodeError::SelfModifying` instead of only reporting
+    pub fault: bool,
+    /// Writes onto code so far, in the order they were found
+    pub writes: Vec<CodeWrite>,
+    executed: AddressMap<bool>,
+    /// Writes to each cell not executed yet, oldest first
+    pending: HashMap<usize, Vec<CodeWrite>>,
+}
+
+impl SelfModDetector {
+    pub fn new() -> Self {
+        SelfModDetector::default()
+    }
+
+    /// Fault on writes to code rather than just reporting them
+    pub fn fault(mut self, fault: bool) -> Self {
+        self.fault = fault;
+        self
+    }
+
+    pub fn is_executed(&self, address: usize) -> bool {
+        self.executed.get(address)
+    }
+}
+
+impl Program {
+    /// Fails before the instruction at `pointer` runs if it would write onto
+    /// executed code or is itself made of modified cells
+    pub(super) fn check_code_write(
+        &self,
+        pointer: usize,
+        arity: usize,
+        target: Option<usize>,
+    ) -> Result<(), IntcodeError> {
+        let detector = match &self.self_mod {
+            Some(detector) if detector.fault => detector,
+            _ => return Ok(()),
+        };
+        let modified = (pointer..=pointer + arity).find(|a| detector.pending.contains_key(a));
+        let span = pointer..=pointer + arity;
+        let written = target.filter(|t| detector.is_executed(*t) || span.contains(t));
+        match modified.or(written) {
+            Some(address) => Err(IntcodeError::SelfModifying {
+                pointer,
+                instruction: self.memory.get(pointer),
+                address,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Marks an executed instruction's cells and records any write it made
+    /// onto code
+    pub(super) fn record_code_write(
+        &mut self,
+        pointer: usize,
+        arity: usize,
+        write: Option<(usize, i64)>,
+        old: Option<i64>,
+    ) {
+        let detector = match self.self_mod.as_mut() {
+            Some(detector) => detector,
+            None => return,
+        };
+        for address in pointer..=pointer + arity {
+            if let Some(writes) = detector.pending.remove(&address) {
+                detector.writes.extend(writes);
+            }
+            *detector.executed.get_mut(address) = true;
+        }
+        if let (Some((address, new)), Some(old)) = (write, old) {
+            let after_execution = detector.is_executed(address);
+            let write = CodeWrite {
+                writer: pointer,
+                address,
+                old,
+                new,
+                after_execution,
+            };
+            if after_execution {
+                detector.writes.push(write);
+            } else {
+                detector.pending.entry(address).or_default().push(write);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::{run_with, FAR_HALT};
+    use crate::intcode::State;
+
+    #[test]
+    fn reports() {
+        // Day 2 example: patches the halt at 4 into a mul, which then
+        // writes over the first instruction
+        let (program, res) = run_with(&[1, 1, 1, 4, 99, 5, 6, 0, 99], &[], |p| {
+            p.self_mod = Some(SelfModDetector::new().fault(false))
+        });
+        assert_eq!(res, Ok(State::Halted));
+        let writes: Vec<String> = program
+            .self_mod
+            .unwrap()
+            .writes
+            .iter()
+            .map(|w| w.to_string())
+            .collect();
+        assert_eq!(
+            writes,
+            [
+                "     0  [4] 99 -> 2  executed later",
+                "     4  [0] 1 -> 30  already executed"
+            ]
+        );
+
+        let (program, _) = run_with(&[1101, 7, 3, 1, 99], &[], |p| {
+            p.self_mod = Some(SelfModDetector::new().fault(false))
+        });
+        assert_eq!(
+            program.self_mod.unwrap().writes,
+            [CodeWrite {
+                writer: 0,
+                address: 1,
+                old: 7,
+                new: 10,
+                after_execution: true
+            }]
+        );
+
+        // Writes the cell at 8 twice before running it
+        let (program, _) = run_with(&[1101, 1, 1, 8, 1101, 99, 0, 8, 0], &[], |p| {
+            p.self_mod = Some(SelfModDetector::new().fault(false))
+        });
+        let writes: Vec<(usize, i64, i64)> = program
+            .self_mod
+            .unwrap()
+            .writes
+            .iter()
+            .map(|w| (w.writer, w.old, w.new))
+            .collect();
+        assert_eq!(writes, [(0, 0, 2), (4, 2, 99)]);
+    }
+
+    #[test]
+    fn faults() {
+        let (program, res) = run_with(&[1101, 7, 3, 1, 99], &[], |p| {
+            p.self_mod = Some(SelfModDetector::new().fault(true))
+        });
+        assert_eq!(
+            res,
+            Err(IntcodeError::SelfModifying {
+                pointer: 0,
+                instruction: 1101,
+                address: 1
+            })
+        );
+        assert_eq!(program.memory.get(1), 7);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "fault at 0 (instruction 1101): self-modifying code at 1"
+        );
+
+        // Writes over the halt, then faults on reaching it
+        let (program, res) = run_with(&[1101, 1, 1, 4, 99], &[], |p| {
+            p.self_mod = Some(SelfModDetector::new().fault(true))
+        });
+        assert_eq!(
+            res,
+            Err(IntcodeError::SelfModifying {
+                pointer: 4,
+                instruction: 2,
+                address: 4
+            })
+        );
+        assert_eq!(program.self_mod.unwrap().writes, []);
+    }
+
+    #[test]
+    fn far_code() {
+        let (program, res) = run_with(&FAR_HALT, &[], |p| {
+            p.self_mod = Some(SelfModDetector::new().fault(false))
+        });
+        assert_eq!(res, Ok(State::Halted));
+        let detector = program.self_mod.unwrap();
+        assert!(detector.is_executed(1_000_000_000_000));
+        assert_eq!(detector.writes.len(), 1);
+        assert_eq!(detector.writes[0].address, 1_000_000_000_000);
+    }
+}
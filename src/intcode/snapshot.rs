@@ -0,0 +1,234 @@
+//! Text snapshots of a whole machine, so a run can be frozen and resumed.
+//!
+//! ```text
+//! intcode snapshot v1
+//! pointer 25
+//! relative_base 1012
+//! state need_input
+//! output 4,2
+//! memory 0 1102,34463338,34463338,63,1007
+//! memory 1024 0,0,7
+//! end
+//! ```
+//!
+//! Each `memory` line holds one page of memory from its first address,
+//! with trailing zeros dropped. Pages that are all zero are left out.
+use super::{Program, State};
+
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+const HEADER: &str = "intcode snapshot";
+const VERSION: &str = "v1";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The first line is not a snapshot header
+    NotASnapshot,
+    UnsupportedVersion(String),
+    /// A malformed line and its 1-based number
+    BadLine(usize, String),
+    /// The input ended before the `end` line
+    Truncated,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::NotASnapshot => write!(f, "not an intcode snapshot"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {:?}", v)
+            }
+            SnapshotError::BadLine(n, line) => write!(f, "line {}: bad line {:?}", n, line),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+fn join(cells: &[i64]) -> String {
+    let cells: Vec<String> = cells.iter().map(|c| c.to_string()).collect();
+    cells.join(",")
+}
+
+fn split(cells: &str) -> Option<Vec<i64>> {
+    if cells.is_empty() {
+        return Some(Vec::new());
+    }
+    cells.split(',').map(|c| c.parse().ok()).collect()
+}
+
+fn parse_state(state: &str) -> Option<State> {
+    match state.split_once(' ') {
+        Some(("output", value)) => value.parse().ok().map(State::Output),
+        None if state == "running" => Some(State::Running),
+        None if state == "need_input" => Some(State::NeedInput),
+        None if state == "halted" => Some(State::Halted),
+        _ => None,
+    }
+}
+
+impl Program {
+    /// Writes a snapshot of memory, registers, state and pending output
+    pub fn save<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{} {}", HEADER, VERSION)?;
+        writeln!(out, "pointer {}", self.pointer)?;
+        writeln!(out, "relative_base {}", self.relative_base)?;
+        match self.state {
+            // Stops that leave the machine ready to carry on
+            State::Running | State::Watchpoint(_) | State::Looping { .. } | State::OutOfFuel(_) => {
+                writeln!(out, "state running")?
+            }
+            State::NeedInput => writeln!(out, "state need_input")?,
+            State::Output(value) => writeln!(out, "state output {}", value)?,
+            State::Halted => writeln!(out, "state halted")?,
+        }
+        if self.output.is_empty() {
+            writeln!(out, "output")?;
+        } else {
+            writeln!(out, "output {}", join(&self.output))?;
+        }
+        for (start, page) in self.memory.pages() {
+            if let Some(last) = page.iter().rposition(|c| *c != 0) {
+                writeln!(out, "memory {} {}", start, join(&page[..=last]))?;
+            }
+        }
+        writeln!(out, "end")?;
+        out.flush()
+    }
+
+    /// Reads a program back from a snapshot written by `save`
+    pub fn load<R: BufRead>(input: R) -> Result<Program, SnapshotError> {
+        let mut lines = input.lines();
+        let header = lines.next().ok_or(SnapshotError::NotASnapshot)??;
+        match header.strip_prefix(HEADER).map(str::trim) {
+            Some(VERSION) => (),
+            Some(version) => return Err(SnapshotError::UnsupportedVersion(version.into())),
+            None => return Err(SnapshotError::NotASnapshot),
+        }
+        let mut program = Program::new(Vec::new(), Vec::new());
+        for (n, line) in lines.enumerate() {
+            let line = line?;
+            let bad = || SnapshotError::BadLine(n + 2, line.clone());
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            match key {
+                "pointer" => program.pointer = value.parse().map_err(|_| bad())?,
+                "relative_base" => program.relative_base = value.parse().map_err(|_| bad())?,
+                "state" => program.state = parse_state(value).ok_or_else(bad)?,
+                "output" => program.output = split(value).ok_or_else(bad)?,
+                "memory" => {
+                    let (start, cells) = value.split_once(' ').ok_or_else(bad)?;
+                    let start: usize = start.parse().map_err(|_| bad())?;
+                    for (i, cell) in split(cells).ok_or_else(bad)?.into_iter().enumerate() {
+                        let address = start.checked_add(i).ok_or_else(bad)?;
+                        program.memory.set(address, cell);
+                    }
+                }
+                "end" => return Ok(program),
+                _ => return Err(bad()),
+            }
+        }
+        Err(SnapshotError::Truncated)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    /// Allocated pages with trailing zeros trimmed, for comparing memories
+    fn nonzero(program: &Program) -> Vec<(usize, Vec<i64>)> {
+        program
+            .memory
+            .pages()
+            .into_iter()
+            .filter_map(|(start, page)| {
+                let last = page.iter().rposition(|c| *c != 0)?;
+                Some((start, page[..=last].to_vec()))
+            })
+            .collect()
+    }
+
+    fn round_trip(program: &Program) -> Program {
+        let mut buf = Vec::new();
+        program.save(&mut buf).unwrap();
+        Program::load(&buf[..]).unwrap()
+    }
+
+    #[test]
+    fn resume_mid_run() {
+        // Outputs twice its input, forever, with its scratch cell far out
+        let data = vec![3, 5000, 1002, 5000, 2, 5000, 4, 5000, 1105, 1, 0];
+        let mut program = Program::new(data, Vec::new());
+        program.relative_base = -3;
+        program
+            .run(&mut VecDeque::from(vec![21, i64::MIN / 2]))
+            .unwrap();
+        let mut copy = round_trip(&program);
+        assert_eq!(copy.pointer, program.pointer);
+        assert_eq!(copy.relative_base, -3);
+        assert_eq!(copy.state, State::NeedInput);
+        assert_eq!(copy.output, [42, i64::MIN]);
+        assert_eq!(nonzero(&copy), nonzero(&program));
+
+        let mut input = VecDeque::from(vec![5]);
+        assert_eq!(
+            copy.run_until_input(&mut input).unwrap(),
+            [42, i64::MIN, 10]
+        );
+    }
+
+    #[test]
+    fn states() {
+        for state in [
+            State::Running,
+            State::NeedInput,
+            State::Output(-7),
+            State::Halted,
+        ]
+        .iter()
+        {
+            let mut program = Program::new(vec![99], Vec::new());
+            program.state = *state;
+            assert_eq!(round_trip(&program).state, *state);
+        }
+    }
+
+    #[test]
+    fn bad_snapshots() {
+        let load = |text: &str| Program::load(text.as_bytes()).unwrap_err().to_string();
+        assert_eq!(load("1,2,3"), "not an intcode snapshot");
+        assert_eq!(
+            load("intcode snapshot v9\nend"),
+            "unsupported snapshot version \"v9\""
+        );
+        assert_eq!(
+            load("intcode snapshot v1\npointer -1\nend"),
+            "line 2: bad line \"pointer -1\""
+        );
+        assert_eq!(
+            load("intcode snapshot v1\nmemory 0 1,x\nend"),
+            "line 2: bad line \"memory 0 1,x\""
+        );
+        assert_eq!(
+            load("intcode snapshot v1\nmemory 18446744073709551615 1,2\nend"),
+            "line 2: bad line \"memory 18446744073709551615 1,2\""
+        );
+        assert_eq!(
+            load("intcode snapshot v1\npointer 4\n"),
+            "snapshot is truncated"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use super::{Executed, Opcode};
+
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// One trace line: pointer, mnemonic, values read, the write if any, and
+/// the relative base after the instruction
+impl fmt::Display for Executed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opcode = self.instruction.opcode;
+        write!(f, "{:>6}  {:<3}", self.pointer, opcode.mnemonic())?;
+        for value in &self.reads[..opcode.reads()] {
+            write!(f, " {}", value)?;
+        }
+        if let Some((address, value)) = self.write {
+            write!(f, " -> [{}] = {}", address, value)?;
+        }
+        write!(f, "  rb {}", self.relative_base)
+    }
+}
+
+/// Writes one line per executed instruction to any writer, optionally
+/// limited to an address range and a set of opcodes
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    addresses: Option<Range<usize>>,
+    opcodes: Option<Vec<Opcode>>,
+    /// First write error, tracing stops once one happens
+    pub error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        Tracer {
+            out: Box::new(out),
+            addresses: None,
+            opcodes: None,
+            error: None,
+        }
+    }
+
+    /// Only trace instructions whose pointer falls in `range`
+    pub fn addresses(mut self, range: Range<usize>) -> Self {
+        self.addresses = Some(range);
+        self
+    }
+
+    /// Only trace instructions with one of these opcodes
+    pub fn opcodes(mut self, opcodes: &[Opcode]) -> Self {
+        self.opcodes = Some(opcodes.to_vec());
+        self
+    }
+
+    pub fn record(&mut self, executed: &Executed) {
+        if self.error.is_some()
+            || !self
+                .addresses
+                .as_ref()
+                .is_none_or(|r| r.contains(&executed.pointer))
+            || !self
+                .opcodes
+                .as_ref()
+                .is_none_or(|ops| ops.contains(&executed.instruction.opcode))
+        {
+            return;
+        }
+        if let Err(e) = writeln!(self.out, "{}", executed) {
+            self.error = Some(e);
+        }
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("addresses", &self.addresses)
+            .field("opcodes", &self.opcodes)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::Program;
+
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer the test can read back after handing it to the tracer
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn traced(tracer: impl FnOnce(Shared) -> Tracer) -> String {
+        let buf = Shared::default();
+        // Reads n, adjusts the base by n, stores n * 3 at rb+6 and outputs it
+        let mut program = Program::new(
+            vec![3, 13, 109, 5, 21002, 13, 3, 6, 204, 6, 99, 0, 0, 0],
+            Vec::new(),
+        );
+        program.tracer = Some(tracer(buf.clone()));
+        program.run(&mut VecDeque::from(vec![7])).unwrap();
+        buf.text()
+    }
+
+    #[test]
+    fn full_trace() {
+        assert_eq!(
+            traced(Tracer::new),
+            "     0  in  -> [13] = 7  rb 0
+     2  arb 5  rb 5
+     4  mul 7 3 -> [11] = 21  rb 5
+     8  out 21  rb 5
+    10  hlt  rb 5
+"
+        );
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            traced(|buf| Tracer::new(buf).addresses(2..9)),
+            "     2  arb 5  rb 5
+     4  mul 7 3 -> [11] = 21  rb 5
+     8  out 21  rb 5
+"
+        );
+        assert_eq!(
+            traced(|buf| Tracer::new(buf)
+                .addresses(2..9)
+                .opcodes(&[Opcode::Input, Opcode::Output])),
+            "     8  out 21  rb 5\n"
+        );
+    }
+}
@@ -0,0 +1,567 @@
+//! Ahead-of-time compilation of a program into a standalone Rust module.
+//! The blocks `cfg::recover` finds become arms of a `match` on the code
+//! pointer, so straight-line code runs natively and only jumps go back
+//! through the `match`.
+//!
+//! Programs often index arrays by writing the address into a parameter of
+//! the instruction using it. Parameters written through constant addresses
+//! are read from memory when the instruction runs. A constant write over
+//! an opcode makes the program not worth compiling and is refused.
+//!
+//! The generated module carries a small interpreter too. It runs whatever
+//! the recovered blocks don't cover, such as targets of computed jumps, and
+//! takes over for good once some other write lands in code.
+//! Arithmetic is checked, as under `Overflow::Checked`.
+use super::cfg::{self, Exit};
+use super::disasm::{Line, Op};
+use super::{Mode, Opcode};
+
+use std::collections::HashSet;
+use std::error;
+use std::fmt::{self, Write};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TranspileError {
+    /// The instruction at `pointer` always writes into the code at
+    /// `address`
+    SelfModifying { pointer: usize, address: usize },
+}
+
+impl fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranspileError::SelfModifying { pointer, address } => write!(
+                f,
+                "instruction at {} writes into code at {}, run it on the interpreter instead",
+                pointer, address
+            ),
+        }
+    }
+}
+
+impl error::Error for TranspileError {}
+
+/// Everything in the generated module but the program, the code mask and
+/// `run`
+const RUNTIME: &str = r#"
+use std::collections::HashMap;
+
+/// The program and cells below this are kept in a flat table, the rest in
+/// a map
+const DENSE_CELLS: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The input callback returned `None`
+    NoInput { pointer: usize },
+    /// A negative address
+    InvalidAddress { pointer: usize, address: i64 },
+    /// A relative address or relative base adjustment that overflows an i64
+    AddressOverflow { pointer: usize },
+    /// A cell that doesn't hold a valid instruction
+    InvalidInstruction { pointer: usize, instruction: i64 },
+    /// An add or mul result that doesn't fit in an i64
+    Overflow { pointer: usize },
+}
+
+fn address(pointer: usize, address: i64) -> Result<usize, Fault> {
+    if address < 0 {
+        return Err(Fault::InvalidAddress { pointer, address });
+    }
+    Ok(address as usize)
+}
+
+struct Machine {
+    memory: Vec<i64>,
+    far: HashMap<usize, i64>,
+    rb: i64,
+    pc: usize,
+    /// Cleared once the program writes into its code, after which the
+    /// compiled blocks are stale and everything is interpreted
+    native: bool,
+}
+
+impl Machine {
+    fn get(&self, address: usize) -> i64 {
+        match self.memory.get(address) {
+            Some(value) => *value,
+            None => self.far.get(&address).copied().unwrap_or(0),
+        }
+    }
+
+    /// Writes a cell, returning whether it held code
+    fn set(&mut self, address: usize, value: i64) -> bool {
+        if address >= self.memory.len() {
+            if address >= DENSE_CELLS {
+                self.far.insert(address, value);
+                return false;
+            }
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+        let code = CODE.get(address) == Some(&true);
+        self.native &= !code;
+        code
+    }
+
+    /// Address `offset` from the relative base, for the instruction at
+    /// `pointer`
+    fn relative(&self, pointer: usize, offset: i64) -> Result<usize, Fault> {
+        let target = self.rb.checked_add(offset);
+        address(pointer, target.ok_or(Fault::AddressOverflow { pointer })?)
+    }
+
+    fn adjust_base(&mut self, pointer: usize, by: i64) -> Result<(), Fault> {
+        self.rb = self.rb.checked_add(by).ok_or(Fault::AddressOverflow { pointer })?;
+        Ok(())
+    }
+
+    /// Address parameter `n` of the current instruction refers to
+    fn param(&self, n: usize, mode: i64) -> Result<usize, Fault> {
+        let param = self.get(self.pc + n);
+        match mode {
+            0 => address(self.pc, param),
+            2 => self.relative(self.pc, param),
+            _ => Err(Fault::InvalidInstruction {
+                pointer: self.pc,
+                instruction: self.get(self.pc),
+            }),
+        }
+    }
+
+    fn value(&self, n: usize, mode: i64) -> Result<i64, Fault> {
+        match mode {
+            1 => Ok(self.get(self.pc + n)),
+            _ => Ok(self.get(self.param(n, mode)?)),
+        }
+    }
+
+    /// Interprets one instruction, returning whether the program halted
+    fn step(
+        &mut self,
+        input: &mut impl FnMut() -> Option<i64>,
+        output: &mut impl FnMut(i64),
+    ) -> Result<bool, Fault> {
+        let pointer = self.pc;
+        let instruction = self.get(pointer);
+        let modes = [
+            instruction / 100 % 10,
+            instruction / 1000 % 10,
+            instruction / 10000 % 10,
+        ];
+        match instruction % 100 {
+            opcode @ (1 | 2 | 7 | 8) => {
+                let (a, b) = (self.value(1, modes[0])?, self.value(2, modes[1])?);
+                let value = match opcode {
+                    1 => a.checked_add(b),
+                    2 => a.checked_mul(b),
+                    7 => Some((a < b) as i64),
+                    _ => Some((a == b) as i64),
+                };
+                let value = value.ok_or(Fault::Overflow { pointer })?;
+                let target = self.param(3, modes[2])?;
+                self.set(target, value);
+                self.pc += 4;
+            }
+            3 => {
+                let target = self.param(1, modes[0])?;
+                let value = input().ok_or(Fault::NoInput { pointer })?;
+                self.set(target, value);
+                self.pc += 2;
+            }
+            4 => {
+                output(self.value(1, modes[0])?);
+                self.pc += 2;
+            }
+            opcode @ (5 | 6) => {
+                let (a, b) = (self.value(1, modes[0])?, self.value(2, modes[1])?);
+                if (a != 0) == (opcode == 5) {
+                    self.pc = address(pointer, b)?;
+                } else {
+                    self.pc += 3;
+                }
+            }
+            9 => {
+                self.adjust_base(pointer, self.value(1, modes[0])?)?;
+                self.pc += 2;
+            }
+            99 => return Ok(true),
+            _ => return Err(Fault::InvalidInstruction { pointer, instruction }),
+        }
+        Ok(false)
+    }
+}
+"#;
+
+/// Indent of statements inside a `match` arm of `run`
+const INDENT: &str = "                    ";
+
+/// A parameter of a compiled instruction
+struct Param {
+    pointer: usize,
+    mode: Mode,
+    value: i64,
+    /// The parameter's cell, if the program patches it while running
+    patched: Option<usize>,
+}
+
+impl Param {
+    /// Rust expression for the parameter as written
+    fn raw(&self) -> String {
+        match self.patched {
+            Some(cell) => format!("m.get({})", cell),
+            None => self.value.to_string(),
+        }
+    }
+
+    /// Rust expression for the address the parameter refers to
+    fn target(&self) -> String {
+        match (self.mode, self.patched) {
+            (Mode::Position, None) if self.value >= 0 => self.value.to_string(),
+            (Mode::Relative, _) => format!("m.relative({}, {})?", self.pointer, self.raw()),
+            _ => format!("address({}, {})?", self.pointer, self.raw()),
+        }
+    }
+
+    /// Rust expression for the value of the parameter
+    fn value(&self) -> String {
+        match self.mode {
+            Mode::Immediate => self.raw(),
+            _ => format!("m.get({})", self.target()),
+        }
+    }
+}
+
+/// Statements running one instruction inside a block
+fn statements(out: &mut String, pointer: usize, opcode: Opcode, params: &[Param], next: usize) {
+    let v = |i: usize| params[i].value();
+    let computed = match opcode {
+        Opcode::Add | Opcode::Mul => Some(format!(
+            "i64::checked_{}({}, {}).ok_or(Fault::Overflow {{ pointer: {} }})?",
+            opcode.mnemonic(),
+            v(0),
+            v(1),
+            pointer
+        )),
+        Opcode::LessThan => Some(format!("({} < {}) as i64", v(0), v(1))),
+        Opcode::Equals => Some(format!("({} == {}) as i64", v(0), v(1))),
+        Opcode::Input => Some(format!(
+            "input().ok_or(Fault::NoInput {{ pointer: {} }})?",
+            pointer
+        )),
+        _ => None,
+    };
+    if let (Some(computed), Some(i)) = (computed, opcode.write_param()) {
+        writeln!(out, "{}let value = {};", INDENT, computed).unwrap();
+        let target = params[i].target();
+        // Leave the block as soon as it rewrites code
+        writeln!(out, "{}if m.set({}, value) {{", INDENT, target).unwrap();
+        writeln!(out, "{}    m.pc = {};", INDENT, next).unwrap();
+        writeln!(out, "{}    continue;", INDENT).unwrap();
+        writeln!(out, "{}}}", INDENT).unwrap();
+        return;
+    }
+    let condition = match opcode {
+        Opcode::JumpIfTrue => format!("{} != 0", v(0)),
+        Opcode::JumpIfFalse => format!("{} == 0", v(0)),
+        Opcode::Output => return writeln!(out, "{}output({});", INDENT, v(0)).unwrap(),
+        Opcode::AdjustBase => {
+            let adjust = format!("m.adjust_base({}, {})?;", pointer, v(0));
+            return writeln!(out, "{}{}", INDENT, adjust).unwrap();
+        }
+        _ => return writeln!(out, "{}return Ok(());", INDENT).unwrap(),
+    };
+    writeln!(out, "{}if {} {{", INDENT, condition).unwrap();
+    writeln!(out, "{}    m.pc = address({}, {})?;", INDENT, pointer, v(1)).unwrap();
+    writeln!(out, "{}    continue;", INDENT).unwrap();
+    writeln!(out, "{}}}", INDENT).unwrap();
+}
+
+/// Turns `program` into the source of a Rust module with a function
+/// `run(input, output) -> Result<(), Fault>`
+pub fn transpile(program: &[i64]) -> Result<String, TranspileError> {
+    let cfg = cfg::recover(program);
+    let lines: Vec<&Line> = cfg.blocks.values().flat_map(|b| &b.lines).collect();
+    let mut code = vec![false; program.len()];
+    let mut opcodes = HashSet::new();
+    for line in lines.iter().filter(|l| matches!(l.op, Op::Instruction(..))) {
+        opcodes.insert(line.address);
+        for cell in &mut code[line.address..line.end()] {
+            *cell = true;
+        }
+    }
+    // Writes to constant addresses in code, as (pointer, cell holding the
+    // address, address)
+    let mut writes = Vec::new();
+    for line in &lines {
+        if let Op::Instruction(instruction, operands) = &line.op {
+            if let Some(i) = instruction.opcode.write_param() {
+                let operand = operands[i];
+                let address = operand.value as usize;
+                if operand.mode == Mode::Position
+                    && operand.value >= 0
+                    && code.get(address) == Some(&true)
+                {
+                    writes.push((line.address, line.address + 1 + i, address));
+                }
+            }
+        }
+    }
+    // Writes into parameters patch them, as array indexing does, unless the
+    // address they write to is itself patched
+    let written: HashSet<usize> = writes.iter().map(|w| w.2).collect();
+    let mut patched = HashSet::new();
+    for &(pointer, cell, address) in &writes {
+        if written.contains(&cell) {
+            continue;
+        }
+        if opcodes.contains(&address) {
+            return Err(TranspileError::SelfModifying { pointer, address });
+        }
+        patched.insert(address);
+    }
+    for address in &patched {
+        code[*address] = false;
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "//! Generated by `intcode transpile` from a {} cell program",
+        program.len()
+    )
+    .unwrap();
+    writeln!(out, "#![allow(unused, clippy::all)]").unwrap();
+    out.push_str(RUNTIME);
+    let cells: Vec<String> = program.iter().map(|c| c.to_string()).collect();
+    writeln!(
+        out,
+        "\nconst PROGRAM: [i64; {}] = [{}];",
+        program.len(),
+        cells.join(", ")
+    )
+    .unwrap();
+    let code: Vec<&str> = code
+        .iter()
+        .map(|c| if *c { "true" } else { "false" })
+        .collect();
+    writeln!(out, "/// Cells holding compiled instructions").unwrap();
+    writeln!(
+        out,
+        "const CODE: [bool; {}] = [{}];",
+        code.len(),
+        code.join(", ")
+    )
+    .unwrap();
+    out.push_str(
+        "
+/// Runs the program, taking input from `input` and passing output to
+/// `output`, until it halts or faults
+pub fn run(
+    mut input: impl FnMut() -> Option<i64>,
+    mut output: impl FnMut(i64),
+) -> Result<(), Fault> {
+    let mut m = Machine {
+        memory: PROGRAM.to_vec(),
+        far: HashMap::new(),
+        rb: 0,
+        pc: 0,
+        native: true,
+    };
+    loop {
+        if m.native {
+            match m.pc {
+",
+    );
+    for block in cfg.blocks.values() {
+        writeln!(out, "                {} => {{", block.start).unwrap();
+        let mut next = block.start;
+        let mut interpret = false;
+        for line in &block.lines {
+            writeln!(out, "{}// {}: {}", INDENT, line.address, line.op).unwrap();
+            next = line.end();
+            match &line.op {
+                Op::Instruction(instruction, operands) => {
+                    let params: Vec<Param> = operands
+                        .iter()
+                        .enumerate()
+                        .map(|(i, operand)| {
+                            let cell = line.address + 1 + i;
+                            Param {
+                                pointer: line.address,
+                                mode: operand.mode,
+                                value: operand.value,
+                                patched: Some(cell).filter(|c| patched.contains(c)),
+                            }
+                        })
+                        .collect();
+                    statements(&mut out, line.address, instruction.opcode, &params, next)
+                }
+                // Leave it to the interpreter to fault on
+                Op::Data(_) => {
+                    writeln!(out, "{}m.pc = {};", INDENT, line.address).unwrap();
+                    interpret = true;
+                }
+            }
+        }
+        if block.exit != Exit::Halt && !interpret {
+            writeln!(out, "{}m.pc = {};", INDENT, next).unwrap();
+            writeln!(out, "{}continue;", INDENT).unwrap();
+        }
+        writeln!(out, "                }}").unwrap();
+    }
+    out.push_str(
+        "                _ => (),
+            }
+        }
+        if m.step(&mut input, &mut output)? {
+            return Ok(());
+        }
+    }
+}
+",
+    );
+    Ok(out)
+}
+
+/// `tests::SAMPLE` transpiled, so the tests can build and run generated
+/// code. Regenerate it with `intcode asm` and `intcode transpile` whenever
+/// the output changes.
+#[cfg(test)]
+#[rustfmt::skip]
+mod sample;
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::asm;
+    use crate::intcode::tests::run_with;
+    use crate::intcode::State;
+
+    /// Sums a table backwards from the input through patched operands,
+    /// stores the sum far out and leaves through a computed jump
+    const SAMPLE: &str = "
+                in [n]
+                arb #1000
+        loop:   jf [n], #done
+                add [n], #table, [load+1]
+                add [n], #table, [sum+2]
+        load:   out [0]
+        sum:    add rb+0, [0], rb+0
+                add [n], #-1, [n]
+                jt #1, #loop
+        done:   out rb+0
+                add rb+0, #0, [1000000000000]
+                out [1000000000000]
+                jt #1, [ret]
+                out #-1
+        end:    hlt
+        n:      data 0
+        table:  data 0, 10, 20, 30
+        ret:    data end";
+
+    #[test]
+    fn runs_like_the_interpreter() {
+        let program = asm::assemble(SAMPLE).unwrap();
+        assert_eq!(
+            transpile(&program).unwrap(),
+            include_str!("transpile/sample.rs")
+        );
+        for n in 0..=3 {
+            let (interpreted, res) = run_with(&program, &[n], |_| ());
+            assert_eq!(res, Ok(State::Halted));
+            let (mut input, mut output) = (Some(n), Vec::new());
+            assert_eq!(sample::run(|| input.take(), |v| output.push(v)), Ok(()));
+            assert_eq!(output, interpreted.output);
+        }
+        let mut output = Vec::new();
+        assert_eq!(
+            sample::run(|| None, |v| output.push(v)),
+            Err(sample::Fault::NoInput { pointer: 0 })
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        let program = asm::assemble(
+            "
+            start:  in [n]
+            loop:   jf [n], #done
+                    add [n], #-1, [n]
+                    out rb+5
+                    jt #1, #loop
+            done:   hlt
+            n:      data 0",
+        )
+        .unwrap();
+        let source = transpile(&program).unwrap();
+        assert!(source
+            .starts_with("//! Generated by `intcode transpile` from a 16 cell program\n#![allow("));
+        assert!(source.contains("const PROGRAM: [i64; 16] = [3, 15, 1006, 15, 14, "));
+        assert!(source.contains("const CODE: [bool; 16] = [true, true, "));
+        assert!(source.contains(", true, false];\n"));
+        assert!(source.contains(
+            "
+                2 => {
+                    // 2: jf [15], #14
+                    if m.get(15) == 0 {
+                        m.pc = address(2, 14)?;
+                        continue;
+                    }
+                    m.pc = 5;
+                    continue;
+                }
+                5 => {
+                    // 5: add [15], #-1, [15]
+                    let value = i64::checked_add(m.get(15), -1).ok_or(Fault::Overflow { pointer: 5 })?;
+                    if m.set(15, value) {
+                        m.pc = 9;
+                        continue;
+                    }
+                    // 9: out rb+5
+                    output(m.get(m.relative(9, 5)?));
+                    // 11: jt #1, #2
+                    if 1 != 0 {
+                        m.pc = address(11, 2)?;
+                        continue;
+                    }
+                    m.pc = 14;
+                    continue;
+                }
+                14 => {
+                    // 14: hlt
+                    return Ok(());
+                }
+"
+        ));
+    }
+
+    #[test]
+    fn patched_operands() {
+        // Points the out at 4 to cell 9 by writing its parameter
+        let source = transpile(&[1101, 9, 0, 5, 4, 0, 99, 0, 0, 42]).unwrap();
+        assert!(source.contains("                    output(m.get(address(4, m.get(5))?));\n"));
+        assert!(source.contains(
+            "const CODE: [bool; 10] = [true, true, true, true, true, false, true, false, false, false];"
+        ));
+    }
+
+    #[test]
+    fn refuses_self_modifying() {
+        // Day 2 example, which ends by writing over its first opcode
+        let program = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        assert_eq!(
+            transpile(&program),
+            Err(TranspileError::SelfModifying {
+                pointer: 4,
+                address: 0
+            })
+        );
+        assert_eq!(
+            transpile(&program).unwrap_err().to_string(),
+            "instruction at 4 writes into code at 0, run it on the interpreter instead"
+        );
+        // Writes through relative addresses are only caught at run time
+        assert!(transpile(&[109, 3, 21101, 1, 1, 0, 99]).is_ok());
+    }
+}
@@ -0,0 +1,254 @@
+//! Generated by `intcode transpile` from a 48 cell program
+#![allow(unused, clippy::all)]
+
+use std::collections::HashMap;
+
+/// The program and cells below this are kept in a flat table, the rest in
+/// a map
+const DENSE_CELLS: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The input callback returned `None`
+    NoInput { pointer: usize },
+    /// A negative address
+    InvalidAddress { pointer: usize, address: i64 },
+    /// A relative address or relative base adjustment that overflows an i64
+    AddressOverflow { pointer: usize },
+    /// A cell that doesn't hold a valid instruction
+    InvalidInstruction { pointer: usize, instruction: i64 },
+    /// An add or mul result that doesn't fit in an i64
+    Overflow { pointer: usize },
+}
+
+fn address(pointer: usize, address: i64) -> Result<usize, Fault> {
+    if address < 0 {
+        return Err(Fault::InvalidAddress { pointer, address });
+    }
+    Ok(address as usize)
+}
+
+struct Machine {
+    memory: Vec<i64>,
+    far: HashMap<usize, i64>,
+    rb: i64,
+    pc: usize,
+    /// Cleared once the program writes into its code, after which the
+    /// compiled blocks are stale and everything is interpreted
+    native: bool,
+}
+
+impl Machine {
+    fn get(&self, address: usize) -> i64 {
+        match self.memory.get(address) {
+            Some(value) => *value,
+            None => self.far.get(&address).copied().unwrap_or(0),
+        }
+    }
+
+    /// Writes a cell, returning whether it held code
+    fn set(&mut self, address: usize, value: i64) -> bool {
+        if address >= self.memory.len() {
+            if address >= DENSE_CELLS {
+                self.far.insert(address, value);
+                return false;
+            }
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+        let code = CODE.get(address) == Some(&true);
+        self.native &= !code;
+        code
+    }
+
+    /// Address `offset` from the relative base, for the instruction at
+    /// `pointer`
+    fn relative(&self, pointer: usize, offset: i64) -> Result<usize, Fault> {
+        let target = self.rb.checked_add(offset);
+        address(pointer, target.ok_or(Fault::AddressOverflow { pointer })?)
+    }
+
+    fn adjust_base(&mut self, pointer: usize, by: i64) -> Result<(), Fault> {
+        self.rb = self.rb.checked_add(by).ok_or(Fault::AddressOverflow { pointer })?;
+        Ok(())
+    }
+
+    /// Address parameter `n` of the current instruction refers to
+    fn param(&self, n: usize, mode: i64) -> Result<usize, Fault> {
+        let param = self.get(self.pc + n);
+        match mode {
+            0 => address(self.pc, param),
+            2 => self.relative(self.pc, param),
+            _ => Err(Fault::InvalidInstruction {
+                pointer: self.pc,
+                instruction: self.get(self.pc),
+            }),
+        }
+    }
+
+    fn value(&self, n: usize, mode: i64) -> Result<i64, Fault> {
+        match mode {
+            1 => Ok(self.get(self.pc + n)),
+            _ => Ok(self.get(self.param(n, mode)?)),
+        }
+    }
+
+    /// Interprets one instruction, returning whether the program halted
+    fn step(
+        &mut self,
+        input: &mut impl FnMut() -> Option<i64>,
+        output: &mut impl FnMut(i64),
+    ) -> Result<bool, Fault> {
+        let pointer = self.pc;
+        let instruction = self.get(pointer);
+        let modes = [
+            instruction / 100 % 10,
+            instruction / 1000 % 10,
+            instruction / 10000 % 10,
+        ];
+        match instruction % 100 {
+            opcode @ (1 | 2 | 7 | 8) => {
+                let (a, b) = (self.value(1, modes[0])?, self.value(2, modes[1])?);
+                let value = match opcode {
+                    1 => a.checked_add(b),
+                    2 => a.checked_mul(b),
+                    7 => Some((a < b) as i64),
+                    _ => Some((a == b) as i64),
+                };
+                let value = value.ok_or(Fault::Overflow { pointer })?;
+                let target = self.param(3, modes[2])?;
+                self.set(target, value);
+                self.pc += 4;
+            }
+            3 => {
+                let target = self.param(1, modes[0])?;
+                let value = input().ok_or(Fault::NoInput { pointer })?;
+                self.set(target, value);
+                self.pc += 2;
+            }
+            4 => {
+                output(self.value(1, modes[0])?);
+                self.pc += 2;
+            }
+            opcode @ (5 | 6) => {
+                let (a, b) = (self.value(1, modes[0])?, self.value(2, modes[1])?);
+                if (a != 0) == (opcode == 5) {
+                    self.pc = address(pointer, b)?;
+                } else {
+                    self.pc += 3;
+                }
+            }
+            9 => {
+                self.adjust_base(pointer, self.value(1, modes[0])?)?;
+                self.pc += 2;
+            }
+            99 => return Ok(true),
+            _ => return Err(Fault::InvalidInstruction { pointer, instruction }),
+        }
+        Ok(false)
+    }
+}
+
+const PROGRAM: [i64; 48] = [3, 42, 109, 1000, 1006, 42, 28, 1001, 42, 43, 16, 1001, 42, 43, 19, 4, 0, 20201, 0, 0, 0, 1001, 42, -1, 42, 1105, 1, 4, 204, 0, 1201, 0, 0, 1000000000000, 4, 1000000000000, 105, 1, 47, 104, -1, 99, 0, 0, 10, 20, 30, 41];
+/// Cells holding compiled instructions
+const CODE: [bool; 48] = [true, true, true, true, true, true, true, true, true, true, true, true, true, true, true, true, false, true, true, false, true, true, true, true, true, true, true, true, true, true, true, true, true, true, true, true, true, true, true, false, false, false, false, false, false, false, false, false];
+
+/// Runs the program, taking input from `input` and passing output to
+/// `output`, until it halts or faults
+pub fn run(
+    mut input: impl FnMut() -> Option<i64>,
+    mut output: impl FnMut(i64),
+) -> Result<(), Fault> {
+    let mut m = Machine {
+        memory: PROGRAM.to_vec(),
+        far: HashMap::new(),
+        rb: 0,
+        pc: 0,
+        native: true,
+    };
+    loop {
+        if m.native {
+            match m.pc {
+                0 => {
+                    // 0: in [42]
+                    let value = input().ok_or(Fault::NoInput { pointer: 0 })?;
+                    if m.set(42, value) {
+                        m.pc = 2;
+                        continue;
+                    }
+                    // 2: arb #1000
+                    m.adjust_base(2, 1000)?;
+                    m.pc = 4;
+                    continue;
+                }
+                4 => {
+                    // 4: jf [42], #28
+                    if m.get(42) == 0 {
+                        m.pc = address(4, 28)?;
+                        continue;
+                    }
+                    m.pc = 7;
+                    continue;
+                }
+                7 => {
+                    // 7: add [42], #43, [16]
+                    let value = i64::checked_add(m.get(42), 43).ok_or(Fault::Overflow { pointer: 7 })?;
+                    if m.set(16, value) {
+                        m.pc = 11;
+                        continue;
+                    }
+                    // 11: add [42], #43, [19]
+                    let value = i64::checked_add(m.get(42), 43).ok_or(Fault::Overflow { pointer: 11 })?;
+                    if m.set(19, value) {
+                        m.pc = 15;
+                        continue;
+                    }
+                    // 15: out [0]
+                    output(m.get(address(15, m.get(16))?));
+                    // 17: add rb+0, [0], rb+0
+                    let value = i64::checked_add(m.get(m.relative(17, 0)?), m.get(address(17, m.get(19))?)).ok_or(Fault::Overflow { pointer: 17 })?;
+                    if m.set(m.relative(17, 0)?, value) {
+                        m.pc = 21;
+                        continue;
+                    }
+                    // 21: add [42], #-1, [42]
+                    let value = i64::checked_add(m.get(42), -1).ok_or(Fault::Overflow { pointer: 21 })?;
+                    if m.set(42, value) {
+                        m.pc = 25;
+                        continue;
+                    }
+                    // 25: jt #1, #4
+                    if 1 != 0 {
+                        m.pc = address(25, 4)?;
+                        continue;
+                    }
+                    m.pc = 28;
+                    continue;
+                }
+                28 => {
+                    // 28: out rb+0
+                    output(m.get(m.relative(28, 0)?));
+                    // 30: add rb+0, #0, [1000000000000]
+                    let value = i64::checked_add(m.get(m.relative(30, 0)?), 0).ok_or(Fault::Overflow { pointer: 30 })?;
+                    if m.set(1000000000000, value) {
+                        m.pc = 34;
+                        continue;
+                    }
+                    // 34: out [1000000000000]
+                    output(m.get(1000000000000));
+                    // 36: jt #1, [47]
+                    if 1 != 0 {
+                        m.pc = address(36, m.get(47))?;
+                        continue;
+                    }
+                    m.pc = 39;
+                    continue;
+                }
+                _ => (),
+            }
+        }
+        if m.step(&mut input, &mut output)? {
+            return Ok(());
+        }
+    }
+}
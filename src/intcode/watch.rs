@@ -0,0 +1,177 @@
+use super::Program;
+
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A watched cell being read or written. Reads have `old == new`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// Pointer of the instruction that touched the cell
+    pub pointer: usize,
+    pub address: usize,
+    pub access: Access,
+    pub old: i64,
+    pub new: i64,
+}
+
+impl fmt::Display for WatchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(f, "read [{}] = {}", self.address, self.new)?,
+            Access::Write => write!(f, "write [{}] {} -> {}", self.address, self.old, self.new)?,
+        }
+        write!(f, " at {}", self.pointer)
+    }
+}
+
+type Callback = Box<dyn FnMut(&WatchEvent) + Send>;
+
+/// Watches a range of memory for reads, writes or both. Firing either calls
+/// the callback or, without one, stops the run with `State::Watchpoint`.
+pub struct Watchpoint {
+    pub addresses: Range<usize>,
+    pub read: bool,
+    pub write: bool,
+    callback: Option<Callback>,
+}
+
+impl Watchpoint {
+    /// Stops on writes to `addresses`
+    pub fn new(addresses: Range<usize>) -> Self {
+        Watchpoint {
+            addresses,
+            read: false,
+            write: true,
+            callback: None,
+        }
+    }
+
+    /// Stops on writes to a single cell
+    pub fn at(address: usize) -> Self {
+        Watchpoint::new(address..address + 1)
+    }
+
+    /// Whether reads fire the watchpoint
+    pub fn reads(mut self, read: bool) -> Self {
+        self.read = read;
+        self
+    }
+
+    /// Whether writes fire the watchpoint
+    pub fn writes(mut self, write: bool) -> Self {
+        self.write = write;
+        self
+    }
+
+    /// Calls `f` on every event instead of stopping
+    pub fn callback<F: FnMut(&WatchEvent) + Send + 'static>(mut self, f: F) -> Self {
+        self.callback = Some(Box::new(f));
+        self
+    }
+
+    /// Handles an event, returning true if the run should stop
+    fn fire(&mut self, event: &WatchEvent) -> bool {
+        let wanted = match event.access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        if !wanted || !self.addresses.contains(&event.address) {
+            return false;
+        }
+        match self.callback.as_mut() {
+            Some(f) => {
+                f(event);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl fmt::Debug for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchpoint")
+            .field("addresses", &self.addresses)
+            .field("read", &self.read)
+            .field("write", &self.write)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+impl Program {
+    /// Passes events to every watchpoint, returning the first that stops
+    pub(super) fn fire_watchpoints(&mut self, events: &[WatchEvent]) -> Option<WatchEvent> {
+        let mut stop = None;
+        for event in events {
+            for watchpoint in self.watchpoints.iter_mut() {
+                if watchpoint.fire(event) && stop.is_none() {
+                    stop = Some(*event);
+                }
+            }
+        }
+        stop
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::intcode::tests::DOUBLER;
+    use crate::intcode::State;
+
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn stop_on_write() {
+        let mut program = Program::new(DOUBLER.to_vec(), Vec::new());
+        program.watchpoints.push(Watchpoint::at(11));
+        let mut input = VecDeque::from(vec![5]);
+        let write = |pointer, old, new| {
+            State::Watchpoint(WatchEvent {
+                pointer,
+                address: 11,
+                access: Access::Write,
+                old,
+                new,
+            })
+        };
+        assert_eq!(program.run(&mut input), Ok(write(0, 0, 5)));
+        assert_eq!(program.pointer, 2);
+        let stopped = program.run(&mut input);
+        assert_eq!(stopped, Ok(write(2, 5, 10)));
+        if let Ok(State::Watchpoint(event)) = stopped {
+            assert_eq!(event.to_string(), "write [11] 5 -> 10 at 2");
+        }
+        assert_eq!(program.run(&mut input), Ok(State::NeedInput));
+        assert_eq!(program.output, [10]);
+    }
+
+    #[test]
+    fn callbacks() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let mut program = Program::new(DOUBLER.to_vec(), Vec::new());
+        program.watchpoints.push(
+            Watchpoint::new(10..12)
+                .reads(true)
+                .writes(false)
+                .callback(move |e| seen.lock().unwrap().push(e.to_string())),
+        );
+        assert_eq!(
+            program.run(&mut VecDeque::from(vec![3])),
+            Ok(State::NeedInput)
+        );
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["read [11] = 3 at 2", "read [11] = 6 at 6"]
+        );
+    }
+}
@@ -14,6 +14,6 @@ mod day4;
 mod day5;
 mod day7;
 mod day9;
-mod intcode;
+pub mod intcode;
 
 aoc_lib! { year = 2019 }